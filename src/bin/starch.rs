@@ -0,0 +1,135 @@
+use shader_starch::prelude_build::*;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: starch [OPTIONS] [ROOT]
+
+Transpiles shaders found in ROOT (default: current directory) and generates a
+Rust source file referencing them.
+
+Options:
+    --src <PATHS>        comma separated source roots
+    --out <PATH>         directory transpiled shaders are written to
+    --generated <PATH>   generated Rust source file
+    --targets <LANGS>    comma separated target languages
+    --check              fail if transpiled shaders or generated sources are stale
+                         instead of writing them
+    -h, --help           print this message
+";
+
+// Options map onto env variables Config::init reads, so they take precedence
+// over starch.yml the same way.
+const OPTIONS: &[(&str, &str)] = &[
+    ("--src", "STARCH_SHADER_SRC"),
+    ("--out", "STARCH_SHADER_OUT"),
+    ("--generated", "STARCH_SHADER_GEN"),
+    ("--targets", "STARCH_SHADER_TARGETS"),
+];
+
+fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+        .init();
+
+    let mut root = None;
+    let mut check = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        if arg == "--check" {
+            check = true;
+            continue;
+        }
+
+        if let Some((_, env_var)) = OPTIONS.iter().find(|(option, _)| *option == arg) {
+            match args.next() {
+                Some(value) => std::env::set_var(env_var, value),
+                None => {
+                    eprintln!("missing value for {}\n\n{}", arg, USAGE);
+                    return ExitCode::FAILURE;
+                }
+            }
+        } else if arg.starts_with('-') || root.is_some() {
+            eprintln!("unexpected argument: {}\n\n{}", arg, USAGE);
+            return ExitCode::FAILURE;
+        } else {
+            root = Some(arg);
+        }
+    }
+
+    // checks leave the project as is, including a missing starch.yml
+    let root = root.as_deref().unwrap_or(".");
+    let config = if check {
+        StarchConfig::load(root)
+    } else {
+        StarchConfig::init(root)
+    };
+
+    let shaders = match Shader::load_shaders(&config) {
+        Ok(shaders) => shaders,
+        Err(err) => {
+            eprintln!("unable to load shaders: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    if check {
+        return check_outputs(&shaders, &config);
+    }
+
+    let data = match shaders.transpile_and_write(&config) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("unable to transpile shaders: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    print_summary(&shaders, &data);
+
+    if let Err(err) = data.generate_sources(&config) {
+        eprintln!("unable to generate sources: {}", err);
+        return ExitCode::FAILURE;
+    }
+    println!("Generated {}", config.generated.display());
+
+    ExitCode::SUCCESS
+}
+
+fn check_outputs(shaders: &Shaders, config: &StarchConfig) -> ExitCode {
+    let (data, mut stale) = match shaders.check_outputs(config) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("unable to transpile shaders: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    print_summary(shaders, &data);
+
+    match data.check_sources(config) {
+        Ok(sources) => stale.extend(sources),
+        Err(err) => {
+            eprintln!("unable to generate sources: {}", err);
+            return ExitCode::FAILURE;
+        }
+    }
+    if stale.is_empty() {
+        println!("Transpiled shaders and generated sources are up to date");
+        return ExitCode::SUCCESS;
+    }
+    eprintln!("stale files:");
+    for path in stale {
+        eprintln!("  {}", path.display());
+    }
+    ExitCode::FAILURE
+}
+
+fn print_summary(shaders: &Shaders, data: &CodegenData) {
+    println!("Processed {} shaders", shaders.len());
+    for lang in ShaderLanguage::ALL {
+        let count = data.includes[lang as usize].len();
+        if count > 0 {
+            println!("  {}: {} files", lang, count);
+        }
+    }
+}
@@ -1,25 +1,503 @@
-use crate::prelude_build::ShaderLanguage;
+use crate::error::ParseLanguageError;
+use crate::language::codegen::GENERATED_BANNER;
+use crate::language::merge::strip_region;
+use crate::prelude_build::{FileSystemProvider, Shader, ShaderLanguage};
+use crate::preprocess::{self, IncludeLimits};
+use crate::source::IGNORE_FILE;
+use crate::util::LogResult;
+use crate::util::{
+    normalize_path, relative_path, warn_feature_disabled, write_atomic, StageExt,
+};
+use naga::proc::{BoundsCheckPolicies, BoundsCheckPolicy};
 use naga::valid::{Capabilities, ValidationFlags, Validator};
+use naga::ShaderStage;
 #[cfg(feature = "config-file")]
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
 #[cfg(feature = "config-file")]
 use std::fs::File;
 #[cfg(feature = "config-file")]
 use std::io::{BufReader, BufWriter};
+use std::num::NonZeroU32;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "config-file", derive(Serialize, Deserialize))]
 pub struct Config {
-    pub src: PathBuf,
+    /// Source roots, `out` is expected to be inside of one of them.
+    #[cfg_attr(feature = "config-file", serde(deserialize_with = "deserialize_paths"))]
+    pub src: Vec<PathBuf>,
     pub out: PathBuf,
     pub generated: PathBuf,
 
     pub targets: Vec<ShaderLanguage>,
-    pub validation_flags: ValidationFlags,
-    pub capabilities: Capabilities,
+    /// Mirror source directories as nested modules in generated code.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub nested_modules: bool,
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub preset: ValidationPreset,
+    /// Overrides validation flags implied by `preset`.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "validation_flag_names"
+        )
+    )]
+    pub validation_flags: Option<ValidationFlags>,
+    /// Overrides capabilities implied by `preset`.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "capability_names"
+        )
+    )]
+    pub capabilities: Option<Capabilities>,
+    /// Capabilities added for shaders with entry points of a given stage.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(default, skip_serializing_if = "StageCapabilities::is_empty")
+    )]
+    pub stage_capabilities: StageCapabilities,
+    /// Module generated sources are wrapped in.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub generated_module: Option<String>,
+    /// Visibility of generated items, e.g. `pub(crate)`.
+    #[cfg_attr(feature = "config-file", serde(default = "default_visibility"))]
+    pub generated_visibility: String,
+    /// Indentation unit of generated sources.
+    #[cfg_attr(feature = "config-file", serde(default = "default_indent"))]
+    pub indent: String,
+    /// Written at the top of generated sources, before the default banner.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub generated_header: Option<String>,
+    /// Merge into the starch managed region of an existing `generated` file
+    /// instead of overwriting it, leaving code outside of the region alone.
+    /// Statics of shaders whose outputs were removed are dropped. Not supported
+    /// with the "runtime-index", "phf" and "generate-tests" features.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub generated_append: bool,
+    /// Write every language module into its own `<language>.rs` file next to
+    /// `generated`, which includes them.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub split_generated: bool,
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub include_path_mode: IncludePathMode,
+    /// Copy preprocessed sources into outputs of their own language instead of
+    /// generating them from naga IR.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub passthrough: bool,
+    /// Write parsed naga IR of every shader into `out/ir`.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub dump_ir: bool,
+    /// Write the WGSL naga generates from non-WGSL sources next to their other
+    /// outputs as `.debug.wgsl` files, whether WGSL is a target or not.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub dump_intermediate_wgsl: bool,
+    /// Also merge all shaders into a single `bundle.wgsl` output, with names of
+    /// each shader prefixed by its own.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub bundle_wgsl: bool,
+    /// Name entry points are emitted under in SPIR-V outputs, the source name is
+    /// kept if unset. GLSL entry points are always called `main`.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub entry_point_name: Option<String>,
+    /// Don't generate outputs in the language of their source, generated sources
+    /// refer to the source file instead. Has no effect with `passthrough`.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub skip_identity_targets: bool,
+    /// How SPIR-V, GLSL and MSL outputs guard against out of bounds accesses.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub bounds_check: BoundsCheck,
+    /// Options of GLSL outputs.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub glsl: GlslOptions,
+    /// Stages of entry points outputs are generated for, all of them if unset.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub stages: Option<Vec<ShaderStage>>,
+    /// Preprocessor defines every GLSL source is parsed with. Defines of shader
+    /// variants take precedence.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub defines: BTreeMap<String, String>,
+    /// Emit a `<NAME>_STAGE` constant for every single stage output, along with
+    /// the `ShaderStage` enum they use.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub stage_constants: bool,
+    /// Emit a `<NAME>_VERTEX_ATTRIBUTES` constant for every vertex output,
+    /// crates including generated sources need wgpu for its `VertexFormat`.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub vertex_attributes: bool,
+    /// Whether outputs are placed in a subdirectory per language or directly in
+    /// `out`.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub layout: OutputLayout,
+    /// Emit `#[repr(C)]` structs matching buffer structs of every shader.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub generated_structs: bool,
+    /// Maximum nesting depth of includes.
+    #[cfg_attr(feature = "config-file", serde(default = "default_include_depth"))]
+    pub max_include_depth: usize,
+    /// Maximum size of a shader source after includes are expanded, in bytes.
+    #[cfg_attr(feature = "config-file", serde(default = "default_expanded_size"))]
+    pub max_expanded_size: usize,
+    /// Directories searched in order for includes not found next to the
+    /// including file. They're read through the source provider, so relative
+    /// ones are relative to the source root.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub include_paths: Vec<PathBuf>,
+    /// Include directive forms expanded in sources.
+    #[cfg_attr(feature = "config-file", serde(default = "default_include_syntax"))]
+    pub include_syntax: Vec<IncludeSyntax>,
+    /// Gzip compress binary outputs, generated sources decompress them with
+    /// `flate2` which the including crate has to depend on.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub compress: bool,
+    /// Re-parse SPIR-V outputs and warn about ones naga can't read back.
+    #[cfg_attr(feature = "config-file", serde(default))]
+    pub verify_spv: bool,
+    /// glslangValidator binary GLSL and HLSL outputs are checked with when
+    /// built with `verify-external`, looked up on `PATH` if unset.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub glslang_path: Option<PathBuf>,
+}
+
+/// How outputs are arranged in `out`.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "config-file", derive(Serialize, Deserialize))]
+pub enum OutputLayout {
+    /// A subdirectory per target language, e.g. `out/spv`.
+    #[default]
+    PerLanguage,
+    /// All outputs directly in `out`, told apart by their extension.
+    Flat,
+}
+
+impl FromStr for OutputLayout {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "perlanguage" | "per_language" | "per-language" => OutputLayout::PerLanguage,
+            "flat" => OutputLayout::Flat,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// Bounds checks injected into generated code, see naga's `BoundsCheckPolicy`.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "config-file", derive(Serialize, Deserialize))]
+pub enum BoundsCheck {
+    /// Out of bounds indices are replaced with in bounds ones.
+    Restrict,
+    /// Out of bounds reads return zero and writes are skipped.
+    ReadZeroSkipWrite,
+    /// No checks are added.
+    #[default]
+    Unchecked,
+}
+
+impl BoundsCheck {
+    pub fn policies(self) -> BoundsCheckPolicies {
+        let policy = match self {
+            BoundsCheck::Restrict => BoundsCheckPolicy::Restrict,
+            BoundsCheck::ReadZeroSkipWrite => BoundsCheckPolicy::ReadZeroSkipWrite,
+            BoundsCheck::Unchecked => BoundsCheckPolicy::Unchecked,
+        };
+        BoundsCheckPolicies {
+            index: policy,
+            buffer: policy,
+            image: policy,
+            ..Default::default()
+        }
+    }
+}
+
+impl FromStr for BoundsCheck {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "restrict" => BoundsCheck::Restrict,
+            "readzeroskipwrite" | "read_zero_skip_write" => {
+                BoundsCheck::ReadZeroSkipWrite
+            }
+            "unchecked" => BoundsCheck::Unchecked,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// How generated sources refer to shader files.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "config-file", derive(Serialize, Deserialize))]
+pub enum IncludePathMode {
+    /// Paths relative to `src`, requires `out` to be inside of it.
+    #[default]
+    Relative,
+    /// Absolute paths, allows `out` to be anywhere.
+    Absolute,
+    /// Outputs are written to a directory in `OUT_DIR` of the running build
+    /// script, named like `out`, and included through `env!("OUT_DIR")`.
+    /// Sources are referred to relatively.
+    OutDir,
+}
+
+/// Stands in for `OUT_DIR` in include paths of outputs with
+/// `IncludePathMode::OutDir`.
+pub(crate) const OUT_DIR_INCLUDE: &str = "$OUT_DIR";
+
+impl FromStr for IncludePathMode {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "relative" => IncludePathMode::Relative,
+            "absolute" => IncludePathMode::Absolute,
+            "outdir" | "out_dir" | "out-dir" => IncludePathMode::OutDir,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// Forms of include directives the preprocessor recognizes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "config-file", derive(Serialize, Deserialize))]
+pub enum IncludeSyntax {
+    /// `@starch::include "path"`.
+    Starch,
+    /// `#include "path"` and `#include <path>`, the latter is only searched for
+    /// in `Config::include_paths`.
+    C,
+}
+
+impl IncludeSyntax {
+    pub fn directive(&self) -> &'static str {
+        match self {
+            IncludeSyntax::Starch => preprocess::INCLUDE_DIRECTIVE,
+            IncludeSyntax::C => preprocess::C_INCLUDE_DIRECTIVE,
+        }
+    }
+}
+
+impl FromStr for IncludeSyntax {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value.trim().to_ascii_lowercase().as_str() {
+            "starch" => IncludeSyntax::Starch,
+            "c" | "#include" => IncludeSyntax::C,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// Capabilities only granted to shaders with entry points of a stage, shaders
+/// with several stages get the union of them.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "config-file", derive(Serialize, Deserialize))]
+pub struct StageCapabilities {
+    #[cfg_attr(
+        feature = "config-file",
+        serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "capability_names"
+        )
+    )]
+    pub vertex: Option<Capabilities>,
+    #[cfg_attr(
+        feature = "config-file",
+        serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "capability_names"
+        )
+    )]
+    pub fragment: Option<Capabilities>,
+    #[cfg_attr(
+        feature = "config-file",
+        serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "capability_names"
+        )
+    )]
+    pub compute: Option<Capabilities>,
+}
+
+impl StageCapabilities {
+    pub fn for_stage(&self, stage: ShaderStage) -> Capabilities {
+        match stage {
+            ShaderStage::Vertex => self.vertex,
+            ShaderStage::Fragment => self.fragment,
+            ShaderStage::Compute => self.compute,
+        }
+        .unwrap_or_else(Capabilities::empty)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertex.is_none() && self.fragment.is_none() && self.compute.is_none()
+    }
+}
+
+/// Options of GLSL outputs, naga's defaults are kept for unset ones.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "config-file", derive(Serialize, Deserialize))]
+pub struct GlslOptions {
+    /// Names of writer flags replacing the default ones, e.g.
+    /// `ADJUST_COORDINATE_SPACE` or `TEXTURE_SHADOW_LOD`.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub writer_flags: Option<Vec<String>>,
+    /// Binding slots resources are assigned to, combined samplers use the slot
+    /// of their texture.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub binding_map: Vec<GlslBinding>,
+    /// Number of views rendered by multiview outputs.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub multiview: Option<NonZeroU32>,
+}
+
+#[cfg(feature = "glsl-out")]
+impl GlslOptions {
+    pub fn writer_flags(&self) -> naga::back::glsl::WriterFlags {
+        use naga::back::glsl::WriterFlags;
+
+        let names = match &self.writer_flags {
+            Some(names) => names,
+            None => return naga::back::glsl::Options::default().writer_flags,
+        };
+        let mut result = WriterFlags::empty();
+        for name in names {
+            match name.trim().to_ascii_uppercase().as_str() {
+                "ADJUST_COORDINATE_SPACE" => {
+                    result |= WriterFlags::ADJUST_COORDINATE_SPACE
+                }
+                "TEXTURE_SHADOW_LOD" => result |= WriterFlags::TEXTURE_SHADOW_LOD,
+                "" => {}
+                _ => log::warn!("Ignoring unknown GLSL writer flag: {}", name),
+            }
+        }
+        result
+    }
+
+    pub fn binding_map(&self) -> naga::back::glsl::BindingMap {
+        self.binding_map
+            .iter()
+            .map(|it| {
+                let binding = naga::ResourceBinding {
+                    group: it.group,
+                    binding: it.binding,
+                };
+                (binding, it.slot)
+            })
+            .collect()
+    }
+}
+
+/// Binding slot of the resource at `group` and `binding`, written as
+/// `group.binding=slot` in environment variables.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "config-file", derive(Serialize, Deserialize))]
+pub struct GlslBinding {
+    pub group: u32,
+    pub binding: u32,
+    pub slot: u8,
+}
+
+impl FromStr for GlslBinding {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (resource, slot) = value.split_once('=').ok_or(())?;
+        let (group, binding) = resource.split_once('.').ok_or(())?;
+        Ok(GlslBinding {
+            group: group.trim().parse().map_err(|_| ())?,
+            binding: binding.trim().parse().map_err(|_| ())?,
+            slot: slot.trim().parse().map_err(|_| ())?,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "config-file", derive(Serialize, Deserialize))]
+pub enum ValidationPreset {
+    /// All validation, no optional capabilities.
+    Strict,
+    /// All validation and capabilities.
+    #[default]
+    Default,
+    /// Skips uniformity and layout checks, allows all capabilities.
+    Permissive,
+}
+
+impl ValidationPreset {
+    pub fn validation_flags(self) -> ValidationFlags {
+        match self {
+            ValidationPreset::Strict | ValidationPreset::Default => {
+                ValidationFlags::all()
+            }
+            ValidationPreset::Permissive => {
+                ValidationFlags::all()
+                    - ValidationFlags::CONTROL_FLOW_UNIFORMITY
+                    - ValidationFlags::STRUCT_LAYOUTS
+            }
+        }
+    }
+
+    pub fn capabilities(self) -> Capabilities {
+        match self {
+            ValidationPreset::Strict => Capabilities::empty(),
+            ValidationPreset::Default | ValidationPreset::Permissive => {
+                Capabilities::all()
+            }
+        }
+    }
+}
+
+impl FromStr for ValidationPreset {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "strict" => ValidationPreset::Strict,
+            "default" => ValidationPreset::Default,
+            "permissive" => ValidationPreset::Permissive,
+            _ => return Err(()),
+        })
+    }
 }
 
 fn env_var_list<K: AsRef<OsStr>>(key: K) -> Option<Vec<String>> {
@@ -28,13 +506,119 @@ fn env_var_list<K: AsRef<OsStr>>(key: K) -> Option<Vec<String>> {
         .map(|it| it.split(',').map(|s| s.to_string()).collect())
 }
 
+/// Maps bitflags to and from lists of their names, so config files don't depend on
+/// bit values. Numeric values are still accepted when reading.
+macro_rules! flag_names {
+    ($module: ident, $flags: ty, [$($name: ident),* $(,)?]) => {
+        pub mod $module {
+            use super::*;
+
+            pub const NAMES: &[(&str, $flags)] = &[$((stringify!($name), <$flags>::$name)),*];
+
+            pub fn to_names(flags: $flags) -> Vec<&'static str> {
+                NAMES
+                    .iter()
+                    .filter(|(_, flag)| flags.contains(*flag))
+                    .map(|(name, _)| *name)
+                    .collect()
+            }
+
+            pub fn from_names<'a>(
+                names: impl IntoIterator<Item = &'a str>,
+            ) -> Result<$flags, String> {
+                let mut result = <$flags>::empty();
+                for name in names {
+                    let name = name.trim();
+                    match NAMES.iter().find(|(it, _)| it.eq_ignore_ascii_case(name)) {
+                        Some((_, flag)) => result |= *flag,
+                        None => return Err(name.to_string()),
+                    }
+                }
+                Ok(result)
+            }
+
+            /// Parses either a comma separated list of names or the numeric value.
+            pub fn parse(value: &str) -> Option<$flags> {
+                if let Ok(bits) = u8::from_str(value.trim()) {
+                    return <$flags>::from_bits(bits);
+                }
+                match from_names(value.split(',').filter(|it| !it.trim().is_empty())) {
+                    Ok(flags) => Some(flags),
+                    Err(name) => {
+                        log::warn!("Ignoring unknown flag: {}", name);
+                        None
+                    }
+                }
+            }
+
+            #[cfg(feature = "config-file")]
+            pub fn serialize<S: serde::Serializer>(
+                value: &Option<$flags>,
+                serializer: S,
+            ) -> Result<S::Ok, S::Error> {
+                value.map(to_names).serialize(serializer)
+            }
+
+            #[cfg(feature = "config-file")]
+            pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<Option<$flags>, D::Error> {
+                #[derive(Deserialize)]
+                #[serde(untagged)]
+                enum Repr {
+                    Bits(u8),
+                    Names(Vec<String>),
+                }
+
+                Ok(match Option::<Repr>::deserialize(deserializer)? {
+                    None => None,
+                    Some(Repr::Bits(bits)) => Some(<$flags>::from_bits(bits).ok_or_else(
+                        || serde::de::Error::custom(format!("invalid flags: {}", bits)),
+                    )?),
+                    Some(Repr::Names(names)) => Some(
+                        from_names(names.iter().map(String::as_str)).map_err(|name| {
+                            serde::de::Error::custom(format!("unknown flag: {}", name))
+                        })?,
+                    ),
+                })
+            }
+        }
+    };
+}
+
+flag_names!(
+    validation_flag_names,
+    ValidationFlags,
+    [
+        EXPRESSIONS,
+        BLOCKS,
+        CONTROL_FLOW_UNIFORMITY,
+        STRUCT_LAYOUTS,
+        CONSTANTS
+    ]
+);
+flag_names!(
+    capability_names,
+    Capabilities,
+    [
+        PUSH_CONSTANT,
+        FLOAT64,
+        PRIMITIVE_INDEX,
+        SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING,
+        UNIFORM_BUFFER_AND_STORAGE_TEXTURE_ARRAY_NON_UNIFORM_INDEXING,
+        SAMPLER_NON_UNIFORM_INDEXING,
+        CLIP_DISTANCE,
+        CULL_DISTANCE
+    ]
+);
+
 macro_rules! path_field {
     ($field: ident, $source: ident, $env_var: literal, $root: ident, $default: literal) => {
         let $field = std::env::var($env_var)
             .ok()
             .map(|value| PathBuf::from(value))
             .or($source.as_ref().map(|c: &Config| c.$field.clone()))
-            .unwrap_or($root.as_ref().join("src").join($default));
+            .unwrap_or($root.join("src").join($default));
     };
 }
 
@@ -45,69 +629,404 @@ impl Default for Config {
 }
 
 impl Config {
-    #[allow(clippy::let_and_return)]
+    /// Reads the config of the project in `root`, from `starch.yml` or the
+    /// manifest, overridden by environment variables. Writes the resolved config
+    /// to `starch.yml` if there was none.
     pub fn init(root: impl AsRef<Path>) -> Config {
         #[cfg(feature = "config-file")]
-        let local: Option<Config> =
-            Config::load_from_file(&root.as_ref().join("starch.yml"));
+        {
+            let local = Config::load_local(root.as_ref());
+            let write = local.is_none();
+            let result = Config::resolve(root.as_ref(), local);
+            // written before outputs are moved into OUT_DIR, which changes
+            // between builds
+            if write {
+                result
+                    .write_to_file(root.as_ref().join("starch.yml"))
+                    .ok_or_log();
+            }
+            result.in_out_dir(std::env::var_os("OUT_DIR").map(PathBuf::from))
+        }
         #[cfg(not(feature = "config-file"))]
-        let local: Option<Config> = None;
+        Config::from_env(root)
+    }
+
+    /// Like `init`, but never writes `starch.yml`.
+    pub fn load(root: impl AsRef<Path>) -> Config {
+        #[cfg(feature = "config-file")]
+        let local = Config::load_local(root.as_ref());
+        #[cfg(not(feature = "config-file"))]
+        let local = None;
+        Config::resolve(root.as_ref(), local)
+            .in_out_dir(std::env::var_os("OUT_DIR").map(PathBuf::from))
+    }
 
-        path_field!(src, local, "STARCH_SHADER_SRC", root, "");
+    #[cfg(feature = "config-file")]
+    fn load_local(root: &Path) -> Option<Config> {
+        Config::load_from_file(root.join("starch.yml"))
+            .or_else(|| Config::load_from_manifest(root))
+    }
+
+    /// Config of the project in `root` from environment variables and defaults
+    /// only. Never reads or writes config files.
+    pub fn from_env(root: impl AsRef<Path>) -> Config {
+        Config::resolve(root.as_ref(), None)
+            .in_out_dir(std::env::var_os("OUT_DIR").map(PathBuf::from))
+    }
+
+    /// Moves outputs of `IncludePathMode::OutDir` configs into a directory in
+    /// `out_dir` named like `out`, so other files of the build script are left
+    /// alone. Falls back to absolute include paths without `out_dir`.
+    fn in_out_dir(mut self, out_dir: Option<PathBuf>) -> Config {
+        if self.include_path_mode != IncludePathMode::OutDir {
+            return self;
+        }
+        match out_dir {
+            Some(dir) => {
+                let name = self.out.file_name().unwrap_or(OsStr::new("starch"));
+                self.out = dir.join(name);
+            }
+            None => {
+                log::warn!("OUT_DIR isn't set, using absolute include paths instead");
+                self.include_path_mode = IncludePathMode::Absolute;
+            }
+        }
+        self
+    }
+
+    /// Resolves every field from its environment variable, then `local`, then
+    /// its default.
+    fn resolve(root: &Path, local: Option<Config>) -> Config {
+        let src: Vec<PathBuf> = env_var_list("STARCH_SHADER_SRC")
+            .map(|env| env.into_iter().map(PathBuf::from).collect())
+            .or_else(|| local.as_ref().map(|l| l.src.clone()))
+            .unwrap_or_else(|| vec![root.join("src")]);
         path_field!(out, local, "STARCH_SHADER_OUT", root, "gen");
         path_field!(generated, local, "STARCH_SHADER_GEN", root, "lib.rs");
 
-        let targets = env_var_list("STARCH_SHADER_TARGETS")
+        let targets: Vec<ShaderLanguage> = env_var_list("STARCH_SHADER_TARGETS")
             .map(|env| {
                 env.into_iter()
-                    .filter_map(|text| ShaderLanguage::from_str(&text).ok())
+                    .filter_map(|text| match ShaderLanguage::from_str(&text) {
+                        Ok(lang) => Some(lang),
+                        Err(ParseLanguageError::Unknown(_)) => {
+                            log::warn!("Ignoring unknown shader target: {}", text);
+                            None
+                        }
+                        Err(ParseLanguageError::FeatureDisabled(lang)) => {
+                            warn_feature_disabled(
+                                format_args!("{} target", lang),
+                                &[lang.output_feature()],
+                            );
+                            None
+                        }
+                    })
                     .collect()
             })
             .or_else(|| local.as_ref().map(|l| l.targets.clone()))
-            .unwrap_or_else(|| {
-                vec![
-                    #[cfg(feature = "spv-out")]
-                    ShaderLanguage::SPV,
-                    #[cfg(feature = "glsl-out")]
-                    ShaderLanguage::GLSL,
-                    #[cfg(feature = "hlsl-out")]
-                    ShaderLanguage::HLSL,
-                    #[cfg(feature = "wgsl-out")]
-                    ShaderLanguage::WGSL,
-                    #[cfg(feature = "msl-out")]
-                    ShaderLanguage::MSL,
-                ]
-            });
+            .unwrap_or_else(default_targets);
+        let targets = targets
+            .into_iter()
+            .filter(|target| {
+                if !target.can_output() {
+                    warn_feature_disabled(
+                        format_args!("{} target", target),
+                        &[target.output_feature()],
+                    );
+                }
+                target.can_output()
+            })
+            .collect();
+
+        let nested_modules = std::env::var("STARCH_SHADER_NESTED_MODULES")
+            .ok()
+            .and_then(|env| bool::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.nested_modules))
+            .unwrap_or(false);
+
+        let preset = std::env::var("STARCH_SHADER_PRESET")
+            .ok()
+            .and_then(|env| ValidationPreset::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.preset))
+            .unwrap_or_default();
 
         let validation_flags = std::env::var("STARCH_SHADER_VALIDATION")
             .ok()
-            .and_then(|env| u8::from_str(&env).ok().and_then(ValidationFlags::from_bits))
-            .or_else(|| local.as_ref().map(|l| l.validation_flags))
-            .unwrap_or(ValidationFlags::all());
+            .and_then(|env| validation_flag_names::parse(&env))
+            .or_else(|| local.as_ref().and_then(|l| l.validation_flags));
 
         let capabilities = std::env::var("STARCH_SHADER_CAPABILITIES")
             .ok()
-            .and_then(|env| u8::from_str(&env).ok().and_then(Capabilities::from_bits))
-            .or_else(|| local.as_ref().map(|l| l.capabilities))
-            .unwrap_or(Capabilities::all());
+            .and_then(|env| capability_names::parse(&env))
+            .or_else(|| local.as_ref().and_then(|l| l.capabilities));
+
+        let stage_capabilities = StageCapabilities {
+            vertex: std::env::var("STARCH_SHADER_VERTEX_CAPABILITIES")
+                .ok()
+                .and_then(|env| capability_names::parse(&env))
+                .or_else(|| local.as_ref().and_then(|l| l.stage_capabilities.vertex)),
+            fragment: std::env::var("STARCH_SHADER_FRAGMENT_CAPABILITIES")
+                .ok()
+                .and_then(|env| capability_names::parse(&env))
+                .or_else(|| local.as_ref().and_then(|l| l.stage_capabilities.fragment)),
+            compute: std::env::var("STARCH_SHADER_COMPUTE_CAPABILITIES")
+                .ok()
+                .and_then(|env| capability_names::parse(&env))
+                .or_else(|| local.as_ref().and_then(|l| l.stage_capabilities.compute)),
+        };
+
+        let generated_module = std::env::var("STARCH_SHADER_MODULE")
+            .ok()
+            .or_else(|| local.as_ref().and_then(|l| l.generated_module.clone()));
+        let generated_visibility = std::env::var("STARCH_SHADER_VISIBILITY")
+            .ok()
+            .or_else(|| local.as_ref().map(|l| l.generated_visibility.clone()))
+            .unwrap_or_else(default_visibility);
+        let indent = std::env::var("STARCH_SHADER_INDENT")
+            .ok()
+            .or_else(|| local.as_ref().map(|l| l.indent.clone()))
+            .unwrap_or_else(default_indent);
+
+        let generated_header = std::env::var("STARCH_SHADER_HEADER")
+            .ok()
+            .or_else(|| local.as_ref().and_then(|l| l.generated_header.clone()));
+        let generated_append = std::env::var("STARCH_SHADER_GENERATED_APPEND")
+            .ok()
+            .and_then(|env| bool::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.generated_append))
+            .unwrap_or(false);
+        let split_generated = std::env::var("STARCH_SHADER_SPLIT_GENERATED")
+            .ok()
+            .and_then(|env| bool::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.split_generated))
+            .unwrap_or(false);
+
+        let include_path_mode = std::env::var("STARCH_SHADER_INCLUDE_PATHS")
+            .ok()
+            .and_then(|env| IncludePathMode::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.include_path_mode))
+            .unwrap_or_default();
+
+        let passthrough = std::env::var("STARCH_SHADER_PASSTHROUGH")
+            .ok()
+            .and_then(|env| bool::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.passthrough))
+            .unwrap_or(false);
+
+        let dump_ir = std::env::var("STARCH_SHADER_DUMP_IR")
+            .ok()
+            .and_then(|env| bool::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.dump_ir))
+            .unwrap_or(false);
+        if dump_ir && !cfg!(feature = "dump-ir") {
+            warn_feature_disabled("dump_ir", &["dump-ir"]);
+        }
+        let dump_intermediate_wgsl =
+            std::env::var("STARCH_SHADER_DUMP_INTERMEDIATE_WGSL")
+                .ok()
+                .and_then(|env| bool::from_str(&env).ok())
+                .or_else(|| local.as_ref().map(|l| l.dump_intermediate_wgsl))
+                .unwrap_or(false);
+        if dump_intermediate_wgsl && !cfg!(feature = "wgsl-out") {
+            warn_feature_disabled("dump_intermediate_wgsl", &["wgsl-out"]);
+        }
+
+        let bundle_wgsl = std::env::var("STARCH_SHADER_BUNDLE_WGSL")
+            .ok()
+            .and_then(|env| bool::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.bundle_wgsl))
+            .unwrap_or(false);
+        if bundle_wgsl && !cfg!(all(feature = "wgsl-in", feature = "wgsl-out")) {
+            warn_feature_disabled("bundle_wgsl", &["wgsl-in", "wgsl-out"]);
+        }
+
+        let entry_point_name = std::env::var("STARCH_SHADER_ENTRY_POINT")
+            .ok()
+            .or_else(|| local.as_ref().and_then(|l| l.entry_point_name.clone()))
+            .filter(|name| !name.is_empty());
+
+        let skip_identity_targets = std::env::var("STARCH_SHADER_SKIP_IDENTITY")
+            .ok()
+            .and_then(|env| bool::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.skip_identity_targets))
+            .unwrap_or(false);
+
+        let bounds_check = std::env::var("STARCH_SHADER_BOUNDS_CHECK")
+            .ok()
+            .and_then(|env| BoundsCheck::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.bounds_check))
+            .unwrap_or_default();
+
+        let local_glsl = local.as_ref().map(|l| l.glsl.clone()).unwrap_or_default();
+        let glsl = GlslOptions {
+            writer_flags: env_var_list("STARCH_SHADER_GLSL_WRITER_FLAGS")
+                .or(local_glsl.writer_flags),
+            binding_map: env_var_list("STARCH_SHADER_GLSL_BINDINGS")
+                .map(|env| {
+                    env.iter()
+                        .filter_map(|it| {
+                            GlslBinding::from_str(it)
+                                .map_err(|_| {
+                                    log::warn!("Ignoring invalid GLSL binding: {}", it)
+                                })
+                                .ok()
+                        })
+                        .collect()
+                })
+                .unwrap_or(local_glsl.binding_map),
+            multiview: std::env::var("STARCH_SHADER_GLSL_MULTIVIEW")
+                .ok()
+                .and_then(|env| NonZeroU32::from_str(&env).ok())
+                .or(local_glsl.multiview),
+        };
+
+        let stages = env_var_list("STARCH_SHADER_STAGES")
+            .map(|env| {
+                env.iter()
+                    .filter_map(|it| {
+                        ShaderStage::from_name(it.trim()).or_else(|| {
+                            log::warn!("Ignoring unknown shader stage: {}", it);
+                            None
+                        })
+                    })
+                    .collect()
+            })
+            .or_else(|| local.as_ref().and_then(|l| l.stages.clone()));
+
+        let defines = env_var_list("STARCH_SHADER_DEFINES")
+            .map(|env| {
+                env.iter()
+                    .filter(|it| !it.trim().is_empty())
+                    .map(|it| match it.split_once('=') {
+                        Some((name, value)) => {
+                            (name.trim().to_string(), value.to_string())
+                        }
+                        None => (it.trim().to_string(), String::new()),
+                    })
+                    .collect()
+            })
+            .or_else(|| local.as_ref().map(|l| l.defines.clone()))
+            .unwrap_or_default();
+
+        let stage_constants = std::env::var("STARCH_SHADER_STAGE_CONSTANTS")
+            .ok()
+            .and_then(|env| bool::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.stage_constants))
+            .unwrap_or(false);
+
+        let layout = std::env::var("STARCH_SHADER_LAYOUT")
+            .ok()
+            .and_then(|env| OutputLayout::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.layout))
+            .unwrap_or_default();
 
-        let result = Config {
+        let vertex_attributes = std::env::var("STARCH_SHADER_VERTEX_ATTRIBUTES")
+            .ok()
+            .and_then(|env| bool::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.vertex_attributes))
+            .unwrap_or(false);
+
+        let generated_structs = std::env::var("STARCH_SHADER_STRUCTS")
+            .ok()
+            .and_then(|env| bool::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.generated_structs))
+            .unwrap_or(false);
+
+        let max_include_depth = std::env::var("STARCH_SHADER_MAX_INCLUDE_DEPTH")
+            .ok()
+            .and_then(|env| usize::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.max_include_depth))
+            .unwrap_or_else(default_include_depth);
+
+        let include_paths: Vec<PathBuf> = env_var_list("STARCH_SHADER_INCLUDE_DIRS")
+            .map(|env| env.into_iter().map(PathBuf::from).collect())
+            .or_else(|| local.as_ref().map(|l| l.include_paths.clone()))
+            .unwrap_or_default();
+
+        let include_syntax: Vec<IncludeSyntax> =
+            env_var_list("STARCH_SHADER_INCLUDE_SYNTAX")
+                .map(|env| {
+                    env.iter()
+                        .filter_map(|it| {
+                            IncludeSyntax::from_str(it)
+                                .map_err(|_| {
+                                    log::warn!("Ignoring unknown include syntax: {}", it)
+                                })
+                                .ok()
+                        })
+                        .collect()
+                })
+                .or_else(|| local.as_ref().map(|l| l.include_syntax.clone()))
+                .unwrap_or_else(default_include_syntax);
+
+        let max_expanded_size = std::env::var("STARCH_SHADER_MAX_EXPANDED_SIZE")
+            .ok()
+            .and_then(|env| usize::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.max_expanded_size))
+            .unwrap_or_else(default_expanded_size);
+
+        let compress = std::env::var("STARCH_SHADER_COMPRESS")
+            .ok()
+            .and_then(|env| bool::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.compress))
+            .unwrap_or(false);
+        if compress && !cfg!(feature = "compress") {
+            warn_feature_disabled("compress", &["compress"]);
+        }
+
+        let verify_spv = std::env::var("STARCH_SHADER_VERIFY_SPV")
+            .ok()
+            .and_then(|env| bool::from_str(&env).ok())
+            .or_else(|| local.as_ref().map(|l| l.verify_spv))
+            .unwrap_or(false);
+        if verify_spv && !cfg!(feature = "spv-in") {
+            warn_feature_disabled("verify_spv", &["spv-in"]);
+        }
+
+        let glslang_path = std::env::var("STARCH_SHADER_GLSLANG")
+            .ok()
+            .map(PathBuf::from)
+            .or_else(|| local.as_ref().and_then(|l| l.glslang_path.clone()));
+
+        Config {
             src,
             out,
             generated,
             targets,
+            nested_modules,
+            preset,
             validation_flags,
             capabilities,
-        };
-
-        #[cfg(feature = "config-file")]
-        {
-            if local.is_none() {
-                result.write_to_file(&root.as_ref().join("starch.yml"));
-            }
+            stage_capabilities,
+            generated_module,
+            generated_visibility,
+            indent,
+            generated_header,
+            generated_append,
+            split_generated,
+            include_path_mode,
+            passthrough,
+            dump_ir,
+            dump_intermediate_wgsl,
+            bundle_wgsl,
+            entry_point_name,
+            skip_identity_targets,
+            bounds_check,
+            glsl,
+            stages,
+            defines,
+            stage_constants,
+            vertex_attributes,
+            layout,
+            generated_structs,
+            max_include_depth,
+            max_expanded_size,
+            include_paths,
+            include_syntax,
+            compress,
+            verify_spv,
+            glslang_path,
         }
-
-        result
     }
 
     #[cfg(feature = "config-file")]
@@ -120,7 +1039,75 @@ impl Config {
 
         let file = File::open(path).ok()?;
         let reader = BufReader::new(file);
-        Some(serde_yaml::from_reader(reader).ok()?)
+        serde_yaml::from_reader(reader).ok()
+    }
+
+    /// Reads `[package.metadata.starch]` from the `Cargo.toml` in `root` or the
+    /// nearest of its parents. Missing fields get the same defaults as in `init`,
+    /// relative paths are resolved against the directory of the manifest.
+    #[cfg(feature = "config-file")]
+    pub fn load_from_manifest(root: impl AsRef<Path>) -> Option<Config> {
+        let root = root.as_ref();
+        let manifest_path = root
+            .ancestors()
+            .map(|dir| dir.join("Cargo.toml"))
+            .find(|path| path.is_file())?;
+
+        let manifest: toml::Value =
+            toml::from_str(&std::fs::read_to_string(&manifest_path).ok()?).ok()?;
+        let mut metadata = manifest
+            .get("package")?
+            .get("metadata")?
+            .get("starch")?
+            .as_table()?
+            .clone();
+
+        // `out` and `generated` default to paths in the primary source root,
+        // which is only known once `src` is resolved
+        let has_out = metadata.contains_key("out");
+        let has_generated = metadata.contains_key("generated");
+        let defaults = [
+            ("src", toml::Value::try_from(vec![root.join("src")])),
+            ("out", toml::Value::try_from(PathBuf::new())),
+            ("generated", toml::Value::try_from(PathBuf::new())),
+            ("targets", toml::Value::try_from(default_targets())),
+        ];
+        for (key, value) in defaults {
+            if !metadata.contains_key(key) {
+                metadata.insert(key.to_string(), value.ok()?);
+            }
+        }
+
+        let mut config: Config = match toml::Value::Table(metadata).try_into() {
+            Ok(config) => config,
+            Err(err) => {
+                log::warn!(
+                    "Ignoring invalid starch metadata in {}: {}",
+                    manifest_path.display(),
+                    err
+                );
+                return None;
+            }
+        };
+
+        let dir = manifest_path.parent()?;
+        config.src = config.src.iter().map(|path| dir.join(path)).collect();
+        let primary = config
+            .src
+            .first()
+            .cloned()
+            .unwrap_or_else(|| root.join("src"));
+        config.out = if has_out {
+            dir.join(&config.out)
+        } else {
+            primary.join("gen")
+        };
+        config.generated = if has_generated {
+            dir.join(&config.generated)
+        } else {
+            primary.join("lib.rs")
+        };
+        Some(config)
     }
 
     #[cfg(feature = "config-file")]
@@ -142,11 +1129,431 @@ impl Config {
             .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
     }
 
+    pub fn validation_flags(&self) -> ValidationFlags {
+        self.validation_flags
+            .unwrap_or_else(|| self.preset.validation_flags())
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+            .unwrap_or_else(|| self.preset.capabilities())
+    }
+
+    /// Capabilities shaders with entry points of `stages` are validated with.
+    pub fn capabilities_for(
+        &self,
+        stages: impl IntoIterator<Item = ShaderStage>,
+    ) -> Capabilities {
+        stages
+            .into_iter()
+            .fold(self.capabilities(), |result, stage| {
+                result | self.stage_capabilities.for_stage(stage)
+            })
+    }
+
+    /// Creates a validator for configured flags and capabilities. Validators keep
+    /// their buffers between calls, so one should be shared across all shaders
+    /// rather than created per shader.
     pub fn validator(&self) -> Validator {
-        Validator::new(self.validation_flags, self.capabilities)
+        Validator::new(self.validation_flags(), self.capabilities())
+    }
+
+    /// Prints `cargo:rerun-if-changed` directives for all shader sources and files
+    /// they include. Meant to be called from build scripts.
+    pub fn emit_rerun_directives(&self) {
+        for root in &self.src {
+            if !root.is_dir() {
+                log::warn!("Shader source directory {} doesn't exist", root.display());
+                continue;
+            }
+            let ignore_file = root.join(IGNORE_FILE);
+            if ignore_file.is_file() {
+                println!("cargo:rerun-if-changed={}", ignore_file.display());
+            }
+
+            let provider = FileSystemProvider::for_root(self, root);
+            for mut shader in Shader::collect(&provider) {
+                preprocess::preprocess_shader_from(&mut shader, self, &provider)
+                    .ok_or_log();
+                for dependency in &shader.dependencies {
+                    println!(
+                        "cargo:rerun-if-changed={}",
+                        root.join(dependency).display()
+                    );
+                }
+                println!(
+                    "cargo:rerun-if-changed={}",
+                    root.join(&shader.path).display()
+                );
+            }
+        }
+    }
+
+    /// Removes the output directory, generated source file and split language
+    /// files next to it. With `generated_append` only the managed region is
+    /// removed from the generated file, code outside of it is kept.
+    pub fn clean(&self) -> std::io::Result<()> {
+        if self.out.exists() {
+            log::info!("Removing: {}", self.out.display());
+            std::fs::remove_dir_all(&self.out)?;
+        }
+        if self.generated.exists() {
+            if self.generated_append {
+                let source = std::fs::read_to_string(&self.generated)?;
+                if let Some(stripped) = strip_region(&source) {
+                    log::info!("Removing managed region: {}", self.generated.display());
+                    write_atomic(&self.generated, stripped)?;
+                }
+            } else {
+                log::info!("Removing: {}", self.generated.display());
+                std::fs::remove_file(&self.generated)?;
+            }
+        }
+        // also left over from before `split_generated` was turned off, files
+        // without the banner aren't ours
+        for lang in ShaderLanguage::ALL {
+            let path = self.split_generated_path(lang);
+            let generated = std::fs::read_to_string(&path)
+                .is_ok_and(|source| source.lines().any(|it| it == GENERATED_BANNER));
+            if generated {
+                log::info!("Removing: {}", path.display());
+                std::fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// File the `lang` module is written to with `split_generated`, next to
+    /// `generated`.
+    pub fn split_generated_path(&self, lang: ShaderLanguage) -> PathBuf {
+        self.generated
+            .with_file_name(format!("{}.rs", lang.to_str()))
+    }
+
+    /// Source root containing `out`, or the first one if none does.
+    pub fn primary_src(&self) -> &Path {
+        self.src
+            .iter()
+            .find(|root| self.out.starts_with(root))
+            .or_else(|| self.src.first())
+            .expect("no source roots configured")
+    }
+
+    fn resolve_root<'a>(&'a self, root: &'a Path) -> &'a Path {
+        if root.as_os_str().is_empty() {
+            self.primary_src()
+        } else {
+            root
+        }
+    }
+
+    /// Targets shaders written in `source_lang` are transpiled into.
+    pub fn targets_for(&self, source_lang: ShaderLanguage) -> Vec<ShaderLanguage> {
+        self.targets
+            .iter()
+            .copied()
+            .filter(|&target| {
+                self.passthrough || !self.skip_identity_targets || target != source_lang
+            })
+            .collect()
     }
 
     pub fn out_relative(&self) -> &Path {
-        self.out.strip_prefix(&self.src).unwrap()
+        self.out
+            .strip_prefix(self.primary_src())
+            .expect("out must be inside of src with relative include paths")
+    }
+
+    /// Path generated sources use to refer to files in source `root`. An empty
+    /// `root` stands for the primary one.
+    pub fn src_include_path(&self, root: &Path) -> PathBuf {
+        let root = self.resolve_root(root);
+        match self.include_path_mode {
+            IncludePathMode::Relative | IncludePathMode::OutDir => {
+                relative_path(root, self.primary_src())
+            }
+            IncludePathMode::Absolute => absolute_path(root),
+        }
+    }
+
+    /// Directory outputs of shaders from `root` are placed in, relative to their
+    /// language output directory. Keeps outputs of secondary roots apart.
+    pub fn output_prefix(&self, root: &Path) -> PathBuf {
+        let root = self.resolve_root(root);
+        if root == self.primary_src() {
+            return PathBuf::new();
+        }
+        root.file_name().map(PathBuf::from).unwrap_or_default()
+    }
+
+    /// Path generated sources use to refer to files in `out`.
+    pub fn out_include_path(&self) -> PathBuf {
+        match self.include_path_mode {
+            IncludePathMode::Relative => self.out_relative().to_path_buf(),
+            IncludePathMode::Absolute => absolute_path(&self.out),
+            IncludePathMode::OutDir => PathBuf::from(OUT_DIR_INCLUDE)
+                .join(self.out.file_name().unwrap_or_default()),
+        }
+    }
+
+    /// Checks configured targets against the features starch was built with.
+    /// `init` drops unsupported targets, this catches ones set afterwards.
+    pub fn validate(&self) -> Result<(), ParseLanguageError> {
+        match self.targets.iter().find(|target| !target.can_output()) {
+            Some(target) => Err(ParseLanguageError::FeatureDisabled(*target)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn include_limits(&self) -> IncludeLimits {
+        IncludeLimits {
+            max_depth: self.max_include_depth,
+            max_size: self.max_expanded_size,
+        }
+    }
+
+    /// Directory `target` outputs are written to.
+    pub fn target_dir(&self, target: ShaderLanguage) -> PathBuf {
+        self.layout_dir(&self.out, target)
+    }
+
+    /// Path generated sources use to refer to the directory of `target` outputs.
+    pub fn target_include_path(&self, target: ShaderLanguage) -> PathBuf {
+        self.layout_dir(&self.out_include_path(), target)
+    }
+
+    pub(crate) fn layout_dir(&self, out: &Path, target: ShaderLanguage) -> PathBuf {
+        match self.layout {
+            OutputLayout::PerLanguage => out.join(target.to_str()),
+            OutputLayout::Flat => out.to_path_buf(),
+        }
+    }
+}
+
+#[cfg(feature = "config-file")]
+fn deserialize_paths<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<PathBuf>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Paths {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+
+    Ok(match Paths::deserialize(deserializer)? {
+        Paths::One(path) => vec![path],
+        Paths::Many(paths) => paths,
+    })
+}
+
+fn default_targets() -> Vec<ShaderLanguage> {
+    vec![
+        #[cfg(feature = "spv-out")]
+        ShaderLanguage::SPV,
+        #[cfg(feature = "glsl-out")]
+        ShaderLanguage::GLSL,
+        #[cfg(feature = "hlsl-out")]
+        ShaderLanguage::HLSL,
+        #[cfg(feature = "wgsl-out")]
+        ShaderLanguage::WGSL,
+        #[cfg(feature = "msl-out")]
+        ShaderLanguage::MSL,
+    ]
+}
+
+fn default_visibility() -> String {
+    "pub".to_string()
+}
+
+fn default_indent() -> String {
+    "    ".to_string()
+}
+
+fn default_include_syntax() -> Vec<IncludeSyntax> {
+    vec![IncludeSyntax::Starch]
+}
+
+fn default_include_depth() -> usize {
+    IncludeLimits::default().max_depth
+}
+
+fn default_expanded_size() -> usize {
+    IncludeLimits::default().max_size
+}
+
+fn absolute_path(path: &Path) -> PathBuf {
+    match std::env::current_dir() {
+        Ok(current) => normalize_path(current.join(path)),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "config-file")]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn manifest_metadata_is_read() {
+        let root = TempDir::new("starch-manifest-metadata").unwrap();
+        std::fs::create_dir_all(root.join("shaders")).unwrap();
+        std::fs::write(
+            root.join("Cargo.toml"),
+            "[package]\n\
+             name = \"example\"\n\
+             \n\
+             [package.metadata.starch]\n\
+             src = \"shaders\"\n\
+             passthrough = true\n",
+        )
+        .unwrap();
+
+        let config = Config::load_from_manifest(root.join("shaders")).unwrap();
+        assert_eq!(config.src, vec![root.join("shaders")]);
+        assert!(config.passthrough);
+        assert_eq!(config.out, root.join("shaders").join("gen"));
+        assert_eq!(config.generated, root.join("shaders").join("lib.rs"));
+        assert_eq!(config.out_relative(), Path::new("gen"));
+        assert_eq!(config.targets, default_targets());
+    }
+
+    #[test]
+    fn from_env_ignores_config_files() {
+        let root = TempDir::new("starch-from-env").unwrap();
+        std::fs::write(
+            root.join("Cargo.toml"),
+            "[package]\n\
+             name = \"example\"\n\
+             \n\
+             [package.metadata.starch]\n\
+             passthrough = true\n",
+        )
+        .unwrap();
+
+        let config = Config::from_env(&root);
+        assert!(!config.passthrough);
+        assert_eq!(config.src, vec![root.join("src")]);
+        assert_eq!(config.out, root.join("src").join("gen"));
+        assert!(!root.join("starch.yml").exists());
+        assert!(Config::init(&root).passthrough);
+    }
+
+    #[test]
+    fn clean_keeps_code_outside_of_managed_region() {
+        let root = TempDir::new("starch-clean-append").unwrap();
+        let config = Config {
+            out: root.join("gen"),
+            generated: root.join("lib.rs"),
+            generated_append: true,
+            ..Config::from_env(&root)
+        };
+        std::fs::create_dir_all(&config.out).unwrap();
+        std::fs::write(
+            &config.generated,
+            "mod other;\n\n\
+             // starch:begin\n\
+             pub mod wgsl {\n\
+             }\n\
+             // starch:end\n\
+             \n\
+             fn main() {}\n",
+        )
+        .unwrap();
+
+        config.clean().unwrap();
+        assert!(!config.out.exists());
+        assert_eq!(
+            std::fs::read_to_string(&config.generated).unwrap(),
+            "mod other;\n\nfn main() {}\n"
+        );
+
+        let overwritten = Config {
+            generated_append: false,
+            ..config
+        };
+        overwritten.clean().unwrap();
+        assert!(!overwritten.generated.exists());
+    }
+
+    #[test]
+    fn clean_removes_split_language_files() {
+        let root = TempDir::new("starch-clean-split").unwrap();
+        let config = Config {
+            out: root.join("gen"),
+            generated: root.join("lib.rs"),
+            split_generated: false,
+            ..Config::from_env(&root)
+        };
+        std::fs::write(
+            &config.generated,
+            "// GENERATED SOURCE FILE. DO NOT EDIT.\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("wgsl.rs"),
+            "// License header\n// GENERATED SOURCE FILE. DO NOT EDIT.\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("glsl.rs"), "fn main() {}\n").unwrap();
+
+        config.clean().unwrap();
+        assert!(!config.generated.exists());
+        assert!(!root.join("wgsl.rs").exists());
+        assert!(root.join("glsl.rs").exists());
+    }
+
+    #[test]
+    fn out_dir_outputs_get_their_own_directory() {
+        let root = PathBuf::from("project");
+        let config = || Config {
+            include_path_mode: IncludePathMode::OutDir,
+            ..Config::resolve(&root, None)
+        };
+
+        let build = config().in_out_dir(Some(PathBuf::from("target/out")));
+        assert_eq!(build.out, PathBuf::from("target/out/gen"));
+        assert_eq!(build.out_include_path(), PathBuf::from("$OUT_DIR/gen"));
+        assert_eq!(
+            build.target_include_path(ShaderLanguage::GLSL),
+            PathBuf::from("$OUT_DIR/gen/glsl")
+        );
+
+        let fallback = config().in_out_dir(None);
+        assert_eq!(fallback.out, root.join("src").join("gen"));
+        assert_eq!(fallback.include_path_mode, IncludePathMode::Absolute);
+    }
+
+    #[test]
+    fn stage_capabilities_are_added() {
+        let root = TempDir::new("starch-stage-capabilities").unwrap();
+        std::fs::write(
+            root.join("Cargo.toml"),
+            "[package]\n\
+             name = \"example\"\n\
+             \n\
+             [package.metadata.starch]\n\
+             preset = \"Strict\"\n\
+             capabilities = [\"PUSH_CONSTANT\"]\n\
+             stage_capabilities = { compute = [\"FLOAT64\"], fragment = [\"PRIMITIVE_INDEX\"] }\n",
+        )
+        .unwrap();
+
+        let config = Config::load_from_manifest(&root).unwrap();
+        assert_eq!(
+            config.capabilities_for([ShaderStage::Vertex]),
+            Capabilities::PUSH_CONSTANT
+        );
+        assert_eq!(
+            config.capabilities_for([ShaderStage::Compute]),
+            Capabilities::PUSH_CONSTANT | Capabilities::FLOAT64
+        );
+        assert_eq!(
+            config.capabilities_for([ShaderStage::Compute, ShaderStage::Fragment]),
+            Capabilities::PUSH_CONSTANT
+                | Capabilities::FLOAT64
+                | Capabilities::PRIMITIVE_INDEX
+        );
     }
 }
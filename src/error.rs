@@ -1,3 +1,4 @@
+use crate::language::transpile::ShaderLanguage;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::path::PathBuf;
@@ -27,10 +28,82 @@ impl<T: Error> From<Vec<T>> for VecErr<T> {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseLanguageError {
+    #[error("unknown shader language: {0}")]
+    Unknown(String),
+    #[error("starch was built without {0} support")]
+    FeatureDisabled(ShaderLanguage),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncludeLimit {
+    Depth(usize),
+    Size(usize),
+}
+
+impl Display for IncludeLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IncludeLimit::Depth(depth) => write!(f, "include depth limit ({})", depth),
+            IncludeLimit::Size(size) => write!(f, "expanded size limit ({} bytes)", size),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PreprocessError {
+    #[error("{limit} exceeded by include chain: {}", display_chain(.chain))]
+    IncludeLimitExceeded {
+        limit: IncludeLimit,
+        chain: Vec<PathBuf>,
+    },
+    #[error("include {path:?} not found, searched: {}", display_list(.searched))]
+    IncludeNotFound {
+        path: String,
+        searched: Vec<PathBuf>,
+    },
+    #[error("unable to read include {}: {source}", .path.display())]
+    IncludeRead {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+fn display_chain(chain: &[PathBuf]) -> String {
+    chain
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+fn display_list(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Debug, Error)]
+pub enum CodegenError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("conflicting shader identifier {name}: {}", display_list(.paths))]
+    IdentifierCollision { name: String, paths: Vec<PathBuf> },
+    #[error("generated module {0} would be empty")]
+    EmptyModule(String),
+    #[error("generated sources can't be appended with the \"{0}\" feature enabled")]
+    AppendUnsupported(&'static str),
+}
+
 #[derive(Debug, Error)]
 pub enum SourceError {
     #[error("unhandled shader stage")]
     UnhandledShaderStage,
+    #[error("parsing {0} sources is not supported")]
+    NotSupported(ShaderLanguage),
     #[cfg(feature = "wgsl-in")]
     #[error(transparent)]
     WGSLParse(#[from] naga::front::wgsl::ParseError),
@@ -42,10 +115,20 @@ pub enum SourceError {
     SPVParse(#[from] naga::front::spv::Error),
     #[error("unable to validate shader: {0}")]
     Validation(PathBuf),
+    #[error("unable to read shader {}: {1}", .0.display())]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("unable to preprocess shader {}: {1}", .0.display())]
+    Preprocess(PathBuf, #[source] PreprocessError),
+    #[error("invalid target: {0}")]
+    Target(#[from] ParseLanguageError),
+    #[error("shader source directory {} doesn't exist", .0.display())]
+    SourceDirMissing(PathBuf),
+    #[error("invalid shader pattern: {0}")]
+    Pattern(#[from] globset::Error),
 }
 
 #[derive(Debug, Error)]
-pub enum TranspileError<'a> {
+pub enum TranspileError {
     #[error("shader has no entry point")]
     NoEntryPoint,
     #[error("source file transpilation not supported")]
@@ -55,9 +138,10 @@ pub enum TranspileError<'a> {
     #[error("unhandled shader stage")]
     UnhandledShaderStage,
 
+    /// Formatted `naga::front::wgsl::Error`, which borrows from the source.
     #[cfg(feature = "wgsl-in")]
-    #[error("{0:?}")]
-    WGSLFront(naga::front::wgsl::Error<'a>),
+    #[error("{0}")]
+    WGSLFront(String),
     #[cfg(feature = "glsl-in")]
     #[error(transparent)]
     GLSLFront(#[from] naga::front::glsl::Error),
@@ -85,8 +169,13 @@ pub enum TranspileError<'a> {
     Io(#[from] std::io::Error),
     #[error(transparent)]
     Parse(#[from] SourceError),
+    #[error("{} rejected by external validator:\n{output}", .path.display())]
+    ExternalValidation { path: PathBuf, output: String },
+}
 
-    #[cfg(not(feature = "wgsl-in"))]
-    #[error("")]
-    _Phantom(&'a ()),
+#[cfg(feature = "wgsl-in")]
+impl From<naga::front::wgsl::Error<'_>> for TranspileError {
+    fn from(err: naga::front::wgsl::Error<'_>) -> Self {
+        TranspileError::WGSLFront(format!("{:?}", err))
+    }
 }
@@ -0,0 +1,259 @@
+//! Merging of several shaders into a single WGSL module.
+
+use crate::error::TranspileError;
+use crate::language::codegen::CodegenData;
+use crate::language::transpile::{
+    transpile_entry, Destination, ShaderFile, ShaderLanguage,
+};
+use crate::shader::{Shader, ShaderCode};
+use crate::util::{file_prefix, sanitize_identifier, write_atomic};
+use naga::valid::Validator;
+use naga::{Module, Type, TypeInner, UniqueArena};
+use std::collections::BTreeSet;
+
+pub const BUNDLE_FILE_NAME: &str = "bundle.wgsl";
+
+/// Merges `shaders` into one WGSL module. Functions, entry points, global
+/// variables, constants and structs of every shader are prefixed with its name,
+/// e.g. `blur_main`, so they can't collide. Shaders that weren't validated are
+/// skipped.
+pub fn bundle(
+    shaders: &[Shader],
+    validator: &mut Validator,
+) -> Result<String, TranspileError> {
+    let mut result = String::new();
+    let mut prefixes = BTreeSet::new();
+    for shader in shaders {
+        if shader.module_info.is_none() {
+            log::warn!(
+                "Leaving unvalidated {} out of bundle",
+                shader.path.display()
+            );
+            continue;
+        }
+
+        let mut prefix = bundle_prefix(shader);
+        let base = prefix.clone();
+        let mut n = 1;
+        while !prefixes.insert(prefix.clone()) {
+            n += 1;
+            prefix = format!("{}{}", base, n);
+        }
+
+        // naga modules can't be cloned, so the shader is copied through WGSL
+        let mut part = Shader::new(shader.path.with_extension("wgsl"))
+            .expect("WGSL sources are supported");
+        part.source = Some(transpile_entry(shader, None, ShaderLanguage::WGSL)?);
+        part.parse()?;
+        namespace(
+            part.module.as_mut().expect("shader was just parsed"),
+            &prefix,
+        );
+        part.validate(validator)?;
+
+        if !result.is_empty() {
+            result.push('\n');
+        }
+        result.push_str(&format!("// {}\n", shader.path.display()));
+        result
+            .push_str(transpile_entry(&part, None, ShaderLanguage::WGSL)?.unwrap_text());
+    }
+
+    // catches anything the renaming missed
+    let mut bundled = Shader::new(BUNDLE_FILE_NAME).expect("WGSL sources are supported");
+    bundled.source = Some(ShaderCode::Text(result));
+    bundled.parse()?;
+    bundled.validate(validator)?;
+
+    Ok(bundled
+        .source
+        .take()
+        .expect("bundle source")
+        .unwrap_text()
+        .to_string())
+}
+
+/// Writes the bundle of `shaders` into the WGSL output directory and registers
+/// it with `data`.
+pub(crate) fn write_bundle(
+    shaders: &[Shader],
+    dest: &Destination,
+    data: &mut CodegenData,
+) -> Result<(), TranspileError> {
+    let config = dest.config;
+    log::info!("Bundling WGSL module...");
+    // the bundle holds entry points of every shader, so it needs capabilities
+    // of all their stages
+    let capabilities = config.capabilities_for(
+        shaders
+            .iter()
+            .flat_map(|shader| shader.entry_points().iter().map(|it| it.stage)),
+    );
+    let mut validator = Validator::new(config.validation_flags(), capabilities);
+    let source = bundle(shaders, &mut validator)?;
+
+    let target_dir = dest.target_dir(ShaderLanguage::WGSL);
+    std::fs::create_dir_all(&target_dir)?;
+    write_atomic(target_dir.join(BUNDLE_FILE_NAME), source)?;
+
+    data.register_result(
+        ShaderLanguage::WGSL,
+        ShaderFile::new(
+            ShaderLanguage::WGSL,
+            config
+                .target_include_path(ShaderLanguage::WGSL)
+                .join(BUNDLE_FILE_NAME),
+        ),
+    );
+    Ok(())
+}
+
+fn bundle_prefix(shader: &Shader) -> String {
+    let mut result = match &shader.name {
+        Some(name) => name.clone(),
+        None => file_prefix(&shader.path)
+            .map(|it| it.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    };
+    if let Some(variant) = &shader.variant {
+        result.push('_');
+        result.push_str(variant);
+    }
+    sanitize_identifier(&result.to_ascii_lowercase())
+}
+
+/// Prefixes names of module scope items of `module` with `prefix`.
+fn namespace(module: &mut Module, prefix: &str) {
+    let rename = |name: &mut Option<String>| {
+        if let Some(name) = name {
+            *name = format!("{}_{}", prefix, name);
+        }
+    };
+
+    for (_, function) in module.functions.iter_mut() {
+        rename(&mut function.name);
+    }
+    for entry_point in &mut module.entry_points {
+        entry_point.name = format!("{}_{}", prefix, entry_point.name);
+        rename(&mut entry_point.function.name);
+    }
+    for (_, global) in module.global_variables.iter_mut() {
+        rename(&mut global.name);
+    }
+    for (_, constant) in module.constants.iter_mut() {
+        rename(&mut constant.name);
+    }
+
+    // Types can't be changed in place. Renaming keeps them distinct, so every
+    // type is reinserted under the handle it had.
+    let mut types = UniqueArena::new();
+    for (handle, ty) in module.types.iter() {
+        let mut name = ty.name.clone();
+        rename(&mut name);
+        let ty = Type {
+            name,
+            inner: copy_type_inner(&ty.inner),
+        };
+        let inserted = types.insert(ty, module.types.get_span(handle));
+        debug_assert_eq!(inserted, handle);
+    }
+    module.types = types;
+}
+
+// TypeInner isn't Clone in naga 0.9
+fn copy_type_inner(inner: &TypeInner) -> TypeInner {
+    match *inner {
+        TypeInner::Scalar { kind, width } => TypeInner::Scalar { kind, width },
+        TypeInner::Vector { size, kind, width } => {
+            TypeInner::Vector { size, kind, width }
+        }
+        TypeInner::Matrix {
+            columns,
+            rows,
+            width,
+        } => TypeInner::Matrix {
+            columns,
+            rows,
+            width,
+        },
+        TypeInner::Atomic { kind, width } => TypeInner::Atomic { kind, width },
+        TypeInner::Pointer { base, space } => TypeInner::Pointer { base, space },
+        TypeInner::ValuePointer {
+            size,
+            kind,
+            width,
+            space,
+        } => TypeInner::ValuePointer {
+            size,
+            kind,
+            width,
+            space,
+        },
+        TypeInner::Array { base, size, stride } => {
+            TypeInner::Array { base, size, stride }
+        }
+        TypeInner::Struct { ref members, span } => TypeInner::Struct {
+            members: members.clone(),
+            span,
+        },
+        TypeInner::Image {
+            dim,
+            arrayed,
+            class,
+        } => TypeInner::Image {
+            dim,
+            arrayed,
+            class,
+        },
+        TypeInner::Sampler { comparison } => TypeInner::Sampler { comparison },
+        TypeInner::BindingArray { base, size } => TypeInner::BindingArray { base, size },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::tests::{fixture_validator, parsed_fixture};
+
+    #[test]
+    fn colliding_shaders_are_bundled() {
+        let root = crate::util::TempDir::new("starch-bundle").unwrap();
+        let mut validator = fixture_validator();
+
+        let mut shaders = vec![];
+        for (dir, factor) in [("a", "2.0"), ("b", "3.0")] {
+            let path = root.join(dir).join("scale.wgsl");
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(
+                &path,
+                format!(
+                    "struct Params {{ factor: f32 }}\n\
+                     @group(0) @binding(0)\n\
+                     var<storage, read_write> data: array<f32>;\n\
+                     fn scale(x: f32) -> f32 {{ return x * {}; }}\n\
+                     @compute @workgroup_size(64)\n\
+                     fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {{\n\
+                     var params: Params = Params({});\n\
+                     data[id.x] = scale(data[id.x]) * params.factor;\n\
+                     }}\n",
+                    factor, factor
+                ),
+            )
+            .unwrap();
+
+            shaders.push(parsed_fixture(&path));
+        }
+
+        let result = bundle(&shaders, &mut validator).expect("couldn't bundle shaders");
+        for name in [
+            "fn scale_cs_main(",
+            "fn scale2_cs_main(",
+            "fn scale_scale(",
+            "fn scale2_scale(",
+            "struct scale_Params",
+            "struct scale2_Params",
+        ] {
+            assert!(result.contains(name), "missing {:?} in:\n{}", name, result);
+        }
+    }
+}
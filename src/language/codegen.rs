@@ -1,35 +1,596 @@
-use crate::config::Config;
+use crate::config::{Config, OUT_DIR_INCLUDE};
+use crate::error::CodegenError;
+use crate::language::merge::merge_region;
+use crate::layout::{format_struct, StructLayout};
 use crate::prelude_build::{ShaderFile, ShaderLanguage};
+use crate::util::{file_prefix, sanitize_identifier, write_atomic, StageExt};
+use naga::ShaderStage;
 use path_slash::PathExt as _;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Write};
-use std::io::Error;
+use std::io::{Error, ErrorKind};
 use std::ops::AddAssign;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+
+pub(crate) const MANIFEST_FILE_NAME: &str = "manifest.json";
+/// First line of generated sources after the configured header.
+pub(crate) const GENERATED_BANNER: &str = "// GENERATED SOURCE FILE. DO NOT EDIT.";
+
+/// Feature emitting items that list every shader. Merging would replace them
+/// with ones listing shaders of the latest run only, so `generated_append`
+/// can't be used with it.
+const INDEX_FEATURE: Option<&str> = if cfg!(feature = "runtime-index") {
+    Some("runtime-index")
+} else if cfg!(feature = "phf") {
+    Some("phf")
+} else if cfg!(feature = "generate-tests") {
+    Some("generate-tests")
+} else {
+    None
+};
 
 #[derive(Debug, Default)]
 pub struct Context {
     indent: usize,
+    visibility: String,
+    stage_constants: bool,
+    vertex_attributes: bool,
 }
 
 fn format_static_statement(
     name: impl AsRef<str>,
     value: impl AsRef<Path>,
-    indent: usize,
+    binary: bool,
+    c: &Context,
 ) -> String {
+    let (ty, include) = if binary {
+        ("[u8]", "include_bytes")
+    } else {
+        ("str", "include_str")
+    };
+
+    // Rust handles fw slash paths properly on windows
+    let path = value.as_ref().to_slash().unwrap();
+    let path = match path
+        .strip_prefix(OUT_DIR_INCLUDE)
+        .filter(|rest| rest.starts_with('/'))
+    {
+        Some(rest) => format!("concat!(env!(\"OUT_DIR\"), \"{}\")", rest),
+        None => format!("\"{}\"", path),
+    };
+
     format!(
-        "{}pub static {}: &'static str = include_str!(\"{}\");\n",
-        "    ".repeat(indent),
+        "{}{} static {}: &'static {} = {}!({});\n",
+        "    ".repeat(c.indent),
+        c.visibility,
         name.as_ref(),
-        // Rust handles fw slash paths properly on windows
-        value.as_ref().to_slash().unwrap(),
+        ty,
+        include,
+        path,
     )
 }
 
+fn format_decompress_fn(name: &str, c: &Context) -> String {
+    let indent = "    ".repeat(c.indent);
+    let mut result = String::new();
+    let _ = writeln!(result, "{}#[allow(non_snake_case)]", indent);
+    let _ = writeln!(
+        result,
+        "{}{} fn {}_decompressed() -> Vec<u8> {{",
+        indent, c.visibility, name
+    );
+    let _ = writeln!(result, "{}    use std::io::Read as _;", indent);
+    let _ = writeln!(result, "{}    let mut result = Vec::new();", indent);
+    let _ = writeln!(
+        result,
+        "{}    ::flate2::read::GzDecoder::new({})",
+        indent, name
+    );
+    let _ = writeln!(result, "{}        .read_to_end(&mut result)", indent);
+    let _ = writeln!(
+        result,
+        "{}        .expect(\"invalid compressed shader\");",
+        indent
+    );
+    let _ = writeln!(result, "{}    result", indent);
+    let _ = writeln!(result, "{}}}", indent);
+    result
+}
+
+fn format_vertex_attributes(name: &str, file: &ShaderFile, c: &Context) -> String {
+    let indent = "    ".repeat(c.indent);
+    let mut result = format!(
+        "{}{} const {}_VERTEX_ATTRIBUTES: &[(u32, ::wgpu::VertexFormat)] = &[\n",
+        indent, c.visibility, name
+    );
+    for attribute in &file.vertex_attributes {
+        let _ = writeln!(
+            result,
+            "{}    ({}, ::wgpu::VertexFormat::{}),",
+            indent, attribute.location, attribute.format
+        );
+    }
+    let _ = writeln!(result, "{}];", indent);
+    result
+}
+
+fn format_stage_enum(visibility: &str) -> String {
+    let mut result = String::new();
+    result.push_str("\n#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]\n");
+    let _ = writeln!(result, "{} enum ShaderStage {{", visibility);
+    for stage in [
+        ShaderStage::Vertex,
+        ShaderStage::Fragment,
+        ShaderStage::Compute,
+    ] {
+        let _ = writeln!(result, "    {},", stage.name());
+    }
+    result.push_str("}\n");
+    result
+}
+
+/// Generated static listed in indices of generated sources.
+#[cfg(any(feature = "runtime-index", feature = "phf", feature = "generate-tests"))]
+#[derive(Debug, Clone)]
+struct IndexEntry {
+    lang: ShaderLanguage,
+    /// Identifier unique within the language, e.g. `POST_BLUR_FRAG`.
+    #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+    id: String,
+    /// Included file, reported when ids collide.
+    #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+    file: PathBuf,
+    /// Path relative to the language module, e.g. `post::BLUR_FRAG`.
+    path: String,
+    #[cfg(feature = "generate-tests")]
+    compressed: bool,
+}
+
+#[cfg(feature = "runtime-index")]
+fn format_runtime_index(entries: &[IndexEntry], visibility: &str) -> String {
+    let mut result = String::new();
+
+    let ids: BTreeSet<&str> = entries.iter().map(|it| it.id.as_str()).collect();
+    result.push_str("\n#[allow(non_camel_case_types)]\n");
+    result.push_str("#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]\n");
+    let _ = writeln!(result, "{} enum ShaderId {{", visibility);
+    for id in ids {
+        let _ = writeln!(result, "    {},", id);
+    }
+    result.push_str("}\n");
+
+    result.push_str("\n#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]\n");
+    let _ = writeln!(result, "{} enum ShaderLanguage {{", visibility);
+    for lang in ShaderLanguage::ALL {
+        let _ = writeln!(result, "    {},", lang.to_uppercase_str());
+    }
+    result.push_str("}\n");
+
+    let _ = writeln!(
+        result,
+        "\n{} fn get(id: ShaderId, lang: ShaderLanguage) -> Option<&'static [u8]> {{",
+        visibility
+    );
+    result.push_str("    match (id, lang) {\n");
+    for IndexEntry { lang, id, path, .. } in entries {
+        let _ = writeln!(
+            result,
+            "        (ShaderId::{}, ShaderLanguage::{}) => Some({}::{}{}),",
+            id,
+            lang.to_uppercase_str(),
+            lang.to_str(),
+            path,
+            if lang.is_binary() { "" } else { ".as_bytes()" },
+        );
+    }
+    result.push_str("        #[allow(unreachable_patterns)]\n");
+    result.push_str("        _ => None,\n");
+    result.push_str("    }\n");
+    result.push_str("}\n");
+
+    result
+}
+
+/// Map of all files in the `lang` module keyed by their lowercase module path,
+/// e.g. `post/blur_frag`. Requires crates including it to depend on `phf`.
+#[cfg(feature = "phf")]
+fn format_phf_map(lang: ShaderLanguage, entries: &[IndexEntry], c: &Context) -> String {
+    let indent = "    ".repeat(c.indent);
+    let mut result = String::new();
+    let _ = writeln!(
+        result,
+        "{}{} static MAP: ::phf::Map<&'static str, &'static [u8]> = ::phf::phf_map! {{",
+        indent, c.visibility
+    );
+    for IndexEntry { path, .. } in entries.iter().filter(|it| it.lang == lang) {
+        let _ = writeln!(
+            result,
+            "{}    \"{}\" => {}{},",
+            indent,
+            path.replace("::", "/").to_ascii_lowercase(),
+            path,
+            if lang.is_binary() { "" } else { ".as_bytes()" },
+        );
+    }
+    let _ = writeln!(result, "{}}};", indent);
+    result
+}
+
+/// Body of generated tests checking `bytes` holds a SPIR-V module.
+#[cfg(feature = "generate-tests")]
+const SPV_CHECKS: &str = "        \
+    assert_eq!(bytes.len() % 4, 0, \"not made of 32-bit words\");
+        assert!(bytes.len() >= 20, \"missing SPIR-V header\");
+        let magic = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        assert!(
+            magic == 0x0723_0203u32.to_le_bytes() || magic == 0x0723_0203u32.to_be_bytes(),
+            \"invalid SPIR-V magic number\"
+        );
+";
+
+/// Tests checking SPIR-V outputs start with the magic number and are made of
+/// whole words, and that text outputs aren't empty.
+#[cfg(feature = "generate-tests")]
+fn format_generated_tests(entries: &[IndexEntry]) -> String {
+    let mut result = String::new();
+    if entries.is_empty() {
+        return result;
+    }
+
+    result.push_str("\n#[cfg(test)]\nmod starch_tests {\n");
+    for (i, entry) in entries.iter().enumerate() {
+        let lang = entry.lang.to_str();
+        if i > 0 {
+            result.push('\n');
+        }
+        result.push_str("    #[test]\n");
+        let _ = writeln!(
+            result,
+            "    fn {}_{}_is_valid() {{",
+            lang,
+            entry.id.to_ascii_lowercase()
+        );
+        if !entry.lang.is_binary() {
+            let _ = writeln!(
+                result,
+                "        assert!(!super::{}::{}.is_empty());",
+                lang, entry.path
+            );
+        } else if entry.compressed {
+            let _ = writeln!(
+                result,
+                "        let bytes: &[u8] = &super::{}::{}_decompressed();",
+                lang, entry.path
+            );
+            result.push_str(SPV_CHECKS);
+        } else {
+            let _ = writeln!(
+                result,
+                "        let bytes: &[u8] = super::{}::{};",
+                lang, entry.path
+            );
+            result.push_str(SPV_CHECKS);
+        }
+        result.push_str("    }\n");
+    }
+    result.push_str("}\n");
+    result
+}
+
+fn qualified_name(file: &ShaderFile, depth: usize) -> String {
+    let parents: Vec<String> = file
+        .path
+        .parent()
+        .map(|parent| {
+            parent
+                .components()
+                .filter_map(|component| match component {
+                    Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let skip = parents.len().saturating_sub(depth);
+
+    let mut result = String::new();
+    for parent in &parents[skip..] {
+        result.push_str(&parent.to_uppercase());
+        result.push('_');
+    }
+    result.push_str(&file.name());
+    sanitize_identifier(&result)
+}
+
+/// Assigns each file a unique identifier, qualifying colliding names with as
+/// many parent directories as needed to tell them apart.
+fn assign_identifiers<'a>(
+    files: impl IntoIterator<Item = &'a ShaderFile>,
+) -> Result<Vec<(String, &'a ShaderFile)>, CodegenError> {
+    let mut by_name: BTreeMap<String, Vec<&ShaderFile>> = BTreeMap::new();
+    for file in files {
+        by_name.entry(file.name()).or_default().push(file);
+    }
+
+    let mut result = vec![];
+    for (name, files) in by_name {
+        if files.len() == 1 {
+            result.push((name, files[0]));
+            continue;
+        }
+
+        let max_depth = files
+            .iter()
+            .map(|file| file.path.components().count())
+            .max()
+            .unwrap_or_default();
+        let resolved = (1..max_depth).find_map(|depth| {
+            let names: Vec<String> = files
+                .iter()
+                .map(|file| qualified_name(file, depth))
+                .collect();
+            let unique: BTreeSet<&String> = names.iter().collect();
+            (unique.len() == names.len()).then_some(names)
+        });
+
+        match resolved {
+            Some(names) => result.extend(names.into_iter().zip(files)),
+            None => {
+                return Err(CodegenError::IdentifierCollision {
+                    name,
+                    paths: files.iter().map(|file| file.path.clone()).collect(),
+                });
+            }
+        }
+    }
+
+    // qualified names can match names of other files, e.g. `A_BLUR` of `a/blur`
+    // and a shader in `a_blur`
+    let mut assigned: BTreeMap<&str, Vec<&ShaderFile>> = BTreeMap::new();
+    for (name, file) in &result {
+        assigned.entry(name.as_str()).or_default().push(*file);
+    }
+    if let Some((name, files)) = assigned.into_iter().find(|(_, files)| files.len() > 1) {
+        return Err(CodegenError::IdentifierCollision {
+            name: name.to_string(),
+            paths: files.iter().map(|file| file.path.clone()).collect(),
+        });
+    }
+
+    result.sort_by_key(|(_, file)| *file);
+    Ok(result)
+}
+
+pub(crate) const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+    "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+    "move", "mut", "pub", "ref", "return", "self", "static", "struct", "super", "trait",
+    "true", "type", "unsafe", "use", "where", "while", "abstract", "become", "box", "do",
+    "final", "macro", "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+fn module_name(dir: &str) -> String {
+    let mut result = sanitize_identifier(&dir.to_lowercase());
+    if RUST_KEYWORDS.contains(&result.as_str()) {
+        result.push('_');
+    }
+    result
+}
+
+#[derive(Debug, Default)]
+struct ModuleTree<'a> {
+    files: Vec<&'a ShaderFile>,
+    children: BTreeMap<String, ModuleTree<'a>>,
+}
+
+impl<'a> ModuleTree<'a> {
+    fn insert(&mut self, module: &[String], file: &'a ShaderFile) {
+        match module.split_first() {
+            Some((first, rest)) => self
+                .children
+                .entry(first.clone())
+                .or_default()
+                .insert(rest, file),
+            None => self.files.push(file),
+        }
+    }
+
+    /// Collects paths of statics emitted for files in this module and its
+    /// children.
+    fn identifiers(
+        &self,
+        module: &[String],
+        result: &mut Vec<String>,
+    ) -> Result<(), CodegenError> {
+        for (name, _) in assign_identifiers(self.files.iter().copied())? {
+            let mut path = module.to_vec();
+            path.push(name);
+            result.push(path.join("::"));
+        }
+        for (name, child) in &self.children {
+            let mut child_module = module.to_vec();
+            child_module.push(name.clone());
+            child.identifiers(&child_module, result)?;
+        }
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        lang: ShaderLanguage,
+        #[cfg(any(
+            feature = "runtime-index",
+            feature = "phf",
+            feature = "generate-tests"
+        ))]
+        module: &[String],
+        c: &mut Context,
+        result: &mut String,
+        #[cfg(any(
+            feature = "runtime-index",
+            feature = "phf",
+            feature = "generate-tests"
+        ))]
+        index_entries: &mut Vec<IndexEntry>,
+    ) -> Result<(), CodegenError> {
+        for (name, include) in assign_identifiers(self.files.iter().copied())? {
+            let _ = result.write_str(&format_static_statement(
+                &name,
+                &include.path,
+                lang.is_binary(),
+                c,
+            ));
+            if include.compressed {
+                result.push_str(&format_decompress_fn(&name, c));
+            }
+            if let Some([x, y, z]) = include.workgroup_size {
+                let _ = writeln!(
+                    result,
+                    "{}{} const {}_WORKGROUP_SIZE: [u32; 3] = [{}, {}, {}];",
+                    "    ".repeat(c.indent),
+                    c.visibility,
+                    name,
+                    x,
+                    y,
+                    z
+                );
+            }
+            if c.vertex_attributes && !include.vertex_attributes.is_empty() {
+                result.push_str(&format_vertex_attributes(&name, include, c));
+            }
+            if let Some(stage) = include.stage.filter(|_| c.stage_constants) {
+                let _ = writeln!(
+                    result,
+                    "{}{} const {}_STAGE: {}ShaderStage = {}ShaderStage::{};",
+                    "    ".repeat(c.indent),
+                    c.visibility,
+                    name,
+                    "super::".repeat(c.indent),
+                    "super::".repeat(c.indent),
+                    stage.name()
+                );
+            }
+            #[cfg(any(
+                feature = "runtime-index",
+                feature = "phf",
+                feature = "generate-tests"
+            ))]
+            {
+                let mut path = module.to_vec();
+                path.push(name.clone());
+                #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                let id = sanitize_identifier(&path.join("_").to_uppercase());
+                // `a_b::C` and `a::b::C` get the same id
+                #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                if let Some(other) = index_entries
+                    .iter()
+                    .find(|other| other.lang == lang && other.id == id)
+                {
+                    return Err(CodegenError::IdentifierCollision {
+                        name: id,
+                        paths: vec![other.file.clone(), include.path.clone()],
+                    });
+                }
+                index_entries.push(IndexEntry {
+                    lang,
+                    #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                    id,
+                    #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                    file: include.path.clone(),
+                    path: path.join("::"),
+                    #[cfg(feature = "generate-tests")]
+                    compressed: include.compressed,
+                });
+            }
+        }
+
+        for (name, child) in &self.children {
+            let _ = writeln!(
+                result,
+                "{}{} mod {} {{",
+                "    ".repeat(c.indent),
+                c.visibility,
+                name
+            );
+            c.indent += 1;
+            #[cfg(any(
+                feature = "runtime-index",
+                feature = "phf",
+                feature = "generate-tests"
+            ))]
+            let child_module = [module, std::slice::from_ref(name)].concat();
+            child.write(
+                lang,
+                #[cfg(any(
+                    feature = "runtime-index",
+                    feature = "phf",
+                    feature = "generate-tests"
+                ))]
+                &child_module,
+                c,
+                result,
+                #[cfg(any(
+                    feature = "runtime-index",
+                    feature = "phf",
+                    feature = "generate-tests"
+                ))]
+                index_entries,
+            )?;
+            c.indent -= 1;
+            let _ = writeln!(result, "{}}}", "    ".repeat(c.indent));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct StructTree<'a> {
+    structs: Vec<&'a StructLayout>,
+    children: BTreeMap<String, StructTree<'a>>,
+}
+
+impl<'a> StructTree<'a> {
+    fn insert(&mut self, module: &[String], structs: &'a [StructLayout]) {
+        match module.split_first() {
+            Some((first, rest)) => self
+                .children
+                .entry(first.clone())
+                .or_default()
+                .insert(rest, structs),
+            None => self.structs.extend(structs),
+        }
+    }
+
+    fn write(&self, c: &mut Context, result: &mut String) {
+        for (i, layout) in self.structs.iter().enumerate() {
+            if i > 0 {
+                result.push('\n');
+            }
+            format_struct(layout, c.indent, &c.visibility, result);
+        }
+        for (name, child) in &self.children {
+            let _ = writeln!(
+                result,
+                "{}{} mod {} {{",
+                "    ".repeat(c.indent),
+                c.visibility,
+                name
+            );
+            c.indent += 1;
+            child.write(c, result);
+            c.indent -= 1;
+            let _ = writeln!(result, "{}}}", "    ".repeat(c.indent));
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct CodegenData {
     pub sources: [BTreeSet<ShaderFile>; ShaderLanguage::COUNT],
     pub includes: [BTreeSet<ShaderFile>; ShaderLanguage::COUNT],
+    /// Buffer struct layouts keyed by the path of the shader using them.
+    pub structs: BTreeMap<PathBuf, Vec<StructLayout>>,
 }
 
 impl CodegenData {
@@ -41,37 +602,424 @@ impl CodegenData {
         self.includes[language as usize].insert(result_file);
     }
 
-    pub fn generate_sources(self, config: &Config) -> Result<(), Error> {
-        let mut c = Context::default();
+    /// Registered source files along with their language.
+    pub fn iter_sources(&self) -> impl Iterator<Item = (ShaderLanguage, &ShaderFile)> {
+        iter_files(&self.sources)
+    }
 
-        let mut result = String::from("// GENERATED SOURCE FILE. DO NOT EDIT.\n");
+    /// Generated output files along with their language.
+    pub fn iter_results(&self) -> impl Iterator<Item = (ShaderLanguage, &ShaderFile)> {
+        iter_files(&self.includes)
+    }
 
+    pub fn register_structs(&mut self, shader: PathBuf, structs: Vec<StructLayout>) {
+        if !structs.is_empty() {
+            self.structs.insert(shader, structs);
+        }
+    }
+
+    /// Writes generated sources into `config.generated`. With `generated_append`
+    /// they're merged into the file instead, see `Config::generated_append`.
+    /// With `split_generated` language modules are written next to it.
+    pub fn generate_sources(self, config: &Config) -> Result<(), CodegenError> {
+        for (path, source) in self.generated_files(config)? {
+            write_atomic(path, source)?;
+        }
+        Ok(())
+    }
+
+    /// Compares files `generate_sources` would write with ones on disk, without
+    /// writing anything. Returns paths of files that are missing or differ.
+    pub fn check_sources(&self, config: &Config) -> Result<Vec<PathBuf>, CodegenError> {
+        Ok(self
+            .generated_files(config)?
+            .into_iter()
+            .filter(|(path, source)| {
+                std::fs::read(path).map_or(true, |existing| existing != source.as_bytes())
+            })
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    /// Paths and contents of generated sources.
+    fn generated_files(
+        &self,
+        config: &Config,
+    ) -> Result<Vec<(PathBuf, String)>, CodegenError> {
+        let (rendered, language_files) =
+            self.render_files(config, config.split_generated)?;
+        let dir = config.generated.parent().unwrap_or_else(|| Path::new(""));
+        let mut result: Vec<(PathBuf, String)> = language_files
+            .into_iter()
+            .map(|(lang, source)| (config.split_generated_path(lang), source))
+            .collect();
+
+        let rendered = if config.generated_append {
+            if let Some(feature) = INDEX_FEATURE {
+                return Err(CodegenError::AppendUnsupported(feature));
+            }
+            let existing = match std::fs::read_to_string(&config.generated) {
+                Ok(existing) => existing,
+                Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+                Err(err) => return Err(err.into()),
+            };
+            merge_region(&existing, &rendered, &|path| {
+                let path = match path.strip_prefix(OUT_DIR_INCLUDE) {
+                    Some(rest) => match std::env::var_os("OUT_DIR") {
+                        Some(out_dir) => {
+                            PathBuf::from(out_dir).join(rest.trim_start_matches('/'))
+                        }
+                        None => return false,
+                    },
+                    None => dir.join(path),
+                };
+                !path.exists()
+            })
+        } else {
+            rendered
+        };
+        result.push((config.generated.clone(), rendered));
+        Ok(result)
+    }
+
+    /// Writes `out/manifest.json` listing every source and the outputs generated
+    /// from it. Paths are the ones generated sources refer to files by.
+    pub fn write_manifest(&self, config: &Config) -> Result<(), Error> {
+        std::fs::create_dir_all(&config.out)?;
+        write_atomic(config.out.join(MANIFEST_FILE_NAME), self.render_manifest())
+    }
+
+    fn render_manifest(&self) -> String {
+        let mut result = String::from("{\n  \"sources\": [");
+        let sources = self.iter_sources().map(|(_, source)| source);
+        for (i, source) in sources.enumerate() {
+            if i > 0 {
+                result.push(',');
+            }
+            let _ = write!(
+                result,
+                "\n    {{\n      \"path\": {},\n      \"language\": \"{}\",\n      \"outputs\": [",
+                json_string(&source.path.to_slash_lossy()),
+                source.language.to_str()
+            );
+
+            let outputs = ShaderLanguage::ALL
+                .into_iter()
+                .flat_map(|lang| self.includes[lang as usize].iter())
+                .filter(|output| output.source.as_ref() == Some(&source.path));
+            for (j, output) in outputs.enumerate() {
+                if j > 0 {
+                    result.push(',');
+                }
+                let _ = write!(
+                    result,
+                    "\n        {{ \"path\": {}, \"language\": \"{}\"",
+                    json_string(&output.path.to_slash_lossy()),
+                    output.language.to_str()
+                );
+                if let Some(stage) = output.stage {
+                    let _ = write!(
+                        result,
+                        ", \"stage\": \"{}\"",
+                        stage.name().to_ascii_lowercase()
+                    );
+                }
+                if let Some([x, y, z]) = output.workgroup_size {
+                    let _ = write!(result, ", \"workgroup_size\": [{}, {}, {}]", x, y, z);
+                }
+                if let Some(listing) = &output.listing {
+                    let _ = write!(
+                        result,
+                        ", \"listing\": {}",
+                        json_string(&listing.to_slash_lossy())
+                    );
+                }
+                result.push_str(" }");
+            }
+            result.push_str("\n      ]\n    }");
+        }
+        result.push_str("\n  ]\n}\n");
+        result
+    }
+
+    /// Files of `lang` arranged into the modules they're emitted in, `None` if
+    /// there are none.
+    fn module_tree(
+        &self,
+        config: &Config,
+        lang: ShaderLanguage,
+    ) -> Option<ModuleTree<'_>> {
+        let includes: BTreeSet<&ShaderFile> = self.sources[lang as usize]
+            .union(&self.includes[lang as usize])
+            .collect();
+        if includes.is_empty() {
+            return None;
+        }
+
+        let mut tree = ModuleTree::default();
+        for include in includes {
+            let module = if config.nested_modules {
+                module_path(config, lang, include)
+            } else {
+                vec![]
+            };
+            tree.insert(&module, include);
+        }
+        Some(tree)
+    }
+
+    /// Paths of the statics generated sources emit for every file, relative to
+    /// their language module, e.g. `post::BLUR_FRAG`.
+    pub fn identifiers(
+        &self,
+        config: &Config,
+    ) -> Result<Vec<(ShaderLanguage, String)>, CodegenError> {
+        let mut result = vec![];
         for lang in ShaderLanguage::ALL {
-            let includes: BTreeSet<&ShaderFile> = self.sources[lang as usize]
-                .union(&self.includes[lang as usize])
-                .collect();
+            if let Some(tree) = self.module_tree(config, lang) {
+                let mut paths = vec![];
+                tree.identifiers(&[], &mut paths)?;
+                result.extend(paths.into_iter().map(|path| (lang, path)));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Builds the generated Rust source without writing it anywhere.
+    pub fn render(&self, config: &Config) -> Result<String, CodegenError> {
+        Ok(self.render_files(config, false)?.0)
+    }
+
+    /// Builds the generated source and, if `split`, separate sources of language
+    /// modules which it includes.
+    fn render_files(
+        &self,
+        config: &Config,
+        split: bool,
+    ) -> Result<(String, Vec<(ShaderLanguage, String)>), CodegenError> {
+        let mut language_files = vec![];
+        let mut c = Context {
+            indent: 0,
+            visibility: config.generated_visibility.clone(),
+            stage_constants: config.stage_constants,
+            vertex_attributes: config.vertex_attributes,
+        };
+
+        let mut result = String::new();
+        #[cfg(any(
+            feature = "runtime-index",
+            feature = "phf",
+            feature = "generate-tests"
+        ))]
+        let mut index_entries: Vec<IndexEntry> = vec![];
 
-            if includes.is_empty() {
-                continue;
+        // configured targets first, in the order they were listed
+        let mut languages: Vec<ShaderLanguage> = vec![];
+        for lang in config.targets.iter().chain(ShaderLanguage::ALL.iter()) {
+            if !languages.contains(lang) {
+                languages.push(*lang);
             }
+        }
+
+        for lang in languages {
+            let tree = match self.module_tree(config, lang) {
+                Some(tree) => tree,
+                None => continue,
+            };
 
-            let _ = result.write_fmt(format_args!("\npub mod {} {{\n", lang.to_str()));
+            let _ = result.write_fmt(format_args!(
+                "\n{} mod {} {{\n",
+                c.visibility,
+                lang.to_str()
+            ));
             c.indent += 1;
 
-            for include in includes {
-                let _ = result.write_str(&format_static_statement(
-                    include.name(),
-                    &include.path,
-                    c.indent,
-                ));
+            let mut module = String::new();
+            tree.write(
+                lang,
+                #[cfg(any(
+                    feature = "runtime-index",
+                    feature = "phf",
+                    feature = "generate-tests"
+                ))]
+                &[],
+                &mut c,
+                &mut module,
+                #[cfg(any(
+                    feature = "runtime-index",
+                    feature = "phf",
+                    feature = "generate-tests"
+                ))]
+                &mut index_entries,
+            )?;
+            #[cfg(feature = "phf")]
+            module.push_str(&format_phf_map(lang, &index_entries, &c));
+
+            if split {
+                let _ = writeln!(result, "    include!(\"{}.rs\");", lang.to_str());
+                let mut source = banner(config);
+                source.push_str(&dedent(&module));
+                if config.indent != "    " {
+                    source = reindent(&source, &config.indent);
+                }
+                language_files.push((lang, source));
+            } else {
+                result.push_str(&module);
             }
 
             c.indent -= 1;
             let _ = result.write_str("}\n");
         }
 
-        std::fs::write(&config.generated, result)
+        if config.stage_constants && !result.is_empty() {
+            result.push_str(&format_stage_enum(&c.visibility));
+        }
+
+        if !self.structs.is_empty() {
+            let mut tree = StructTree::default();
+            for (path, structs) in &self.structs {
+                let mut module: Vec<String> = path
+                    .parent()
+                    .into_iter()
+                    .flat_map(Path::components)
+                    .filter_map(|component| match component {
+                        Component::Normal(name) => {
+                            Some(module_name(&name.to_string_lossy()))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                module.push(module_name(
+                    &file_prefix(path).unwrap_or_default().to_string_lossy(),
+                ));
+                tree.insert(&module, structs);
+            }
+
+            let _ = write!(result, "\n{} mod structs {{\n", c.visibility);
+            c.indent += 1;
+            tree.write(&mut c, &mut result);
+            c.indent -= 1;
+            result.push_str("}\n");
+        }
+
+        #[cfg(feature = "runtime-index")]
+        result.push_str(&format_runtime_index(&index_entries, &c.visibility));
+        #[cfg(feature = "generate-tests")]
+        result.push_str(&format_generated_tests(&index_entries));
+
+        if config.indent != "    " {
+            result = reindent(&result, &config.indent);
+        }
+
+        let mut output = banner(config);
+        match &config.generated_module {
+            Some(module) => {
+                if result.is_empty() {
+                    return Err(CodegenError::EmptyModule(module.clone()));
+                }
+                let _ = write!(output, "\n{} mod {} {{", c.visibility, module);
+                for line in result.trim_start_matches('\n').lines() {
+                    output.push('\n');
+                    if !line.is_empty() {
+                        output.push_str(&config.indent);
+                        output.push_str(line);
+                    }
+                }
+                output.push_str("\n}\n");
+            }
+            None => output.push_str(&result),
+        }
+
+        Ok((output, language_files))
+    }
+}
+
+/// Configured header followed by the generated file banner.
+fn banner(config: &Config) -> String {
+    let mut result = String::new();
+    if let Some(header) = &config.generated_header {
+        result.push_str(header);
+        if !header.ends_with('\n') {
+            result.push('\n');
+        }
+    }
+    result.push_str(GENERATED_BANNER);
+    result.push('\n');
+    result
+}
+
+/// Removes one level of four space indentation from `source`.
+fn dedent(source: &str) -> String {
+    source
+        .split_inclusive('\n')
+        .map(|line| line.strip_prefix("    ").unwrap_or(line))
+        .collect()
+}
+
+/// Replaces each level of four space indentation in `source` with `unit`.
+fn reindent(source: &str, unit: &str) -> String {
+    let mut result = String::with_capacity(source.len());
+    for line in source.split_inclusive('\n') {
+        let mut rest = line;
+        while let Some(stripped) = rest.strip_prefix("    ") {
+            result.push_str(unit);
+            rest = stripped;
+        }
+        result.push_str(rest);
     }
+    result
+}
+
+fn iter_files(
+    files: &[BTreeSet<ShaderFile>; ShaderLanguage::COUNT],
+) -> impl Iterator<Item = (ShaderLanguage, &ShaderFile)> {
+    ShaderLanguage::ALL
+        .into_iter()
+        .flat_map(move |lang| files[lang as usize].iter().map(move |file| (lang, file)))
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(result, "\\u{:04x}", c as u32);
+            }
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Module path of a file, mirroring its directory relative to the source root
+/// or the language output directory.
+fn module_path(config: &Config, lang: ShaderLanguage, file: &ShaderFile) -> Vec<String> {
+    let mut prefixes = vec![config.target_include_path(lang)];
+    prefixes.extend(config.src.iter().map(|root| config.src_include_path(root)));
+    // most specific prefix first, the primary root might be empty
+    prefixes.sort_by_key(|prefix| std::cmp::Reverse(prefix.components().count()));
+
+    let relative = prefixes
+        .iter()
+        .find_map(|prefix| file.path.strip_prefix(prefix).ok())
+        .unwrap_or(&file.path);
+
+    relative
+        .parent()
+        .map(|parent| {
+            parent
+                .components()
+                .filter_map(|component| match component {
+                    Component::Normal(name) => Some(module_name(&name.to_string_lossy())),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
 impl AddAssign for CodegenData {
@@ -80,5 +1028,692 @@ impl AddAssign for CodegenData {
             self.sources[lang as usize].append(&mut rhs.sources[lang as usize]);
             self.includes[lang as usize].append(&mut rhs.includes[lang as usize]);
         }
+        self.structs.append(&mut rhs.structs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IncludePathMode;
+    use crate::layout::VertexAttribute;
+    use crate::util::TempDir;
+    use std::path::PathBuf;
+
+    fn config() -> Config {
+        Config {
+            src: vec![PathBuf::from("src")],
+            out: PathBuf::from("src").join("gen"),
+            generated: PathBuf::from("src").join("lib.rs"),
+            targets: vec![],
+            nested_modules: true,
+            include_path_mode: IncludePathMode::Relative,
+            ..Config::from_env("")
+        }
+    }
+
+    fn file(
+        language: ShaderLanguage,
+        path: &[&str],
+        stage: Option<ShaderStage>,
+    ) -> ShaderFile {
+        ShaderFile {
+            stage,
+            ..ShaderFile::new(language, path.iter().collect::<PathBuf>())
+        }
+    }
+
+    /// Index entries as `(lang, id, path)`, in the order statics are emitted.
+    type Entries<'a> = [(ShaderLanguage, &'a str, &'a str)];
+
+    #[cfg(any(feature = "runtime-index", feature = "phf", feature = "generate-tests"))]
+    fn index_entries(entries: &Entries) -> Vec<IndexEntry> {
+        entries
+            .iter()
+            .map(|(lang, _id, path)| IndexEntry {
+                lang: *lang,
+                #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                id: _id.to_string(),
+                #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                file: PathBuf::from(path),
+                path: path.to_string(),
+                #[cfg(feature = "generate-tests")]
+                compressed: false,
+            })
+            .collect()
+    }
+
+    /// phf map rendered at the end of the `lang` module, empty without the
+    /// "phf" feature.
+    fn phf_map(lang: ShaderLanguage, entries: &Entries, indent: usize) -> String {
+        #[cfg(feature = "phf")]
+        return format_phf_map(
+            lang,
+            &index_entries(entries),
+            &Context {
+                indent,
+                visibility: "pub".to_string(),
+                ..Default::default()
+            },
+        );
+        #[cfg(not(feature = "phf"))]
+        {
+            let _ = (lang, entries, indent);
+            String::new()
+        }
+    }
+
+    /// Runtime index and tests rendered after all modules, depending on
+    /// enabled features.
+    fn indices(entries: &Entries) -> String {
+        let _ = entries;
+        let result = String::new();
+        #[cfg(feature = "runtime-index")]
+        let result = result + &format_runtime_index(&index_entries(entries), "pub");
+        #[cfg(feature = "generate-tests")]
+        let result = result + &format_generated_tests(&index_entries(entries));
+        result
+    }
+
+    #[test]
+    fn render_is_deterministic() {
+        let mut data = CodegenData::default();
+        data.register_source(
+            ShaderLanguage::WGSL,
+            file(ShaderLanguage::WGSL, &["post", "blur.wgsl"], None),
+        );
+        data.register_source(
+            ShaderLanguage::WGSL,
+            file(ShaderLanguage::WGSL, &["triangle.wgsl"], None),
+        );
+        data.register_result(
+            ShaderLanguage::GLSL,
+            file(
+                ShaderLanguage::GLSL,
+                &["gen", "glsl", "triangle.vert.glsl"],
+                Some(ShaderStage::Vertex),
+            ),
+        );
+        let mut compute = file(
+            ShaderLanguage::SPV,
+            &["gen", "spv", "post", "blur.c.spv"],
+            Some(ShaderStage::Compute),
+        );
+        compute.workgroup_size = Some([8, 8, 1]);
+        data.register_result(ShaderLanguage::SPV, compute);
+
+        let entries: &Entries = &[
+            (ShaderLanguage::WGSL, "TRIANGLE", "TRIANGLE"),
+            (ShaderLanguage::WGSL, "POST_BLUR", "post::BLUR"),
+            (ShaderLanguage::GLSL, "TRIANGLE_VERT", "TRIANGLE_VERT"),
+            (ShaderLanguage::SPV, "POST_BLUR_COMP", "post::BLUR_COMP"),
+        ];
+        let expected = format!(
+            "\
+// GENERATED SOURCE FILE. DO NOT EDIT.
+
+pub mod wgsl {{
+    pub static TRIANGLE: &'static str = include_str!(\"triangle.wgsl\");
+    pub mod post {{
+        pub static BLUR: &'static str = include_str!(\"post/blur.wgsl\");
+    }}
+{}}}
+
+pub mod glsl {{
+    pub static TRIANGLE_VERT: &'static str = include_str!(\"gen/glsl/triangle.vert.glsl\");
+{}}}
+
+pub mod spv {{
+    pub mod post {{
+        pub static BLUR_COMP: &'static [u8] = include_bytes!(\"gen/spv/post/blur.c.spv\");
+        pub const BLUR_COMP_WORKGROUP_SIZE: [u32; 3] = [8, 8, 1];
+    }}
+{}}}
+{}",
+            phf_map(ShaderLanguage::WGSL, entries, 1),
+            phf_map(ShaderLanguage::GLSL, entries, 1),
+            phf_map(ShaderLanguage::SPV, entries, 1),
+            indices(entries)
+        );
+        let rendered = data.render(&config()).unwrap();
+        assert_eq!(rendered, expected);
+        assert_eq!(data.render(&config()).unwrap(), rendered);
+    }
+
+    #[test]
+    fn identifiers_match_emitted_statics() {
+        let mut data = CodegenData::default();
+        data.register_source(
+            ShaderLanguage::WGSL,
+            file(ShaderLanguage::WGSL, &["post", "blur.wgsl"], None),
+        );
+        data.register_result(
+            ShaderLanguage::GLSL,
+            file(
+                ShaderLanguage::GLSL,
+                &["gen", "glsl", "a", "blur.frag.glsl"],
+                Some(ShaderStage::Fragment),
+            ),
+        );
+        data.register_result(
+            ShaderLanguage::GLSL,
+            file(
+                ShaderLanguage::GLSL,
+                &["gen", "glsl", "b", "blur.frag.glsl"],
+                Some(ShaderStage::Fragment),
+            ),
+        );
+
+        assert_eq!(
+            data.identifiers(&config()).unwrap(),
+            vec![
+                (ShaderLanguage::WGSL, "post::BLUR".to_string()),
+                (ShaderLanguage::GLSL, "a::BLUR_FRAG".to_string()),
+                (ShaderLanguage::GLSL, "b::BLUR_FRAG".to_string()),
+            ]
+        );
+
+        let flat = Config {
+            nested_modules: false,
+            ..config()
+        };
+        let identifiers = data.identifiers(&flat).unwrap();
+        let rendered = data.render(&flat).unwrap();
+        assert_eq!(
+            identifiers[1],
+            (ShaderLanguage::GLSL, "A_BLUR_FRAG".to_string())
+        );
+        for (_, path) in identifiers {
+            assert!(rendered.contains(&format!(" static {}:", path)));
+        }
+    }
+
+    #[test]
+    fn modules_follow_target_order() {
+        let mut data = CodegenData::default();
+        data.register_source(
+            ShaderLanguage::WGSL,
+            file(ShaderLanguage::WGSL, &["triangle.wgsl"], None),
+        );
+        for (lang, ext) in [(ShaderLanguage::GLSL, "glsl"), (ShaderLanguage::SPV, "spv")]
+        {
+            data.register_result(
+                lang,
+                file(lang, &["gen", ext, &format!("triangle.{}", ext)], None),
+            );
+        }
+        let ordered = Config {
+            targets: vec![ShaderLanguage::SPV, ShaderLanguage::GLSL],
+            ..config()
+        };
+
+        let rendered = data.render(&ordered).unwrap();
+        let position =
+            |module: &str| rendered.find(&format!("mod {} {{", module)).unwrap();
+        assert!(position("spv") < position("glsl"));
+        assert!(position("glsl") < position("wgsl"));
+    }
+
+    #[test]
+    fn stage_constants_are_emitted() {
+        let mut data = CodegenData::default();
+        data.register_result(
+            ShaderLanguage::GLSL,
+            file(
+                ShaderLanguage::GLSL,
+                &["gen", "glsl", "post", "blur.frag.glsl"],
+                Some(ShaderStage::Fragment),
+            ),
+        );
+        let staged = Config {
+            stage_constants: true,
+            ..config()
+        };
+
+        let rendered = data.render(&staged).unwrap();
+        assert!(rendered.contains(
+            "pub const BLUR_FRAG_STAGE: super::super::ShaderStage = \
+             super::super::ShaderStage::Fragment;"
+        ));
+        assert!(rendered.contains("pub enum ShaderStage {"));
+        assert!(!data.render(&config()).unwrap().contains("_STAGE"));
+    }
+
+    #[test]
+    fn vertex_attributes_are_emitted() {
+        let mut data = CodegenData::default();
+        let mut vertex = file(
+            ShaderLanguage::SPV,
+            &["gen", "spv", "triangle.vert.spv"],
+            Some(ShaderStage::Vertex),
+        );
+        vertex.vertex_attributes = vec![
+            VertexAttribute {
+                location: 0,
+                format: "Float32x3",
+                size: 12,
+            },
+            VertexAttribute {
+                location: 1,
+                format: "Uint32",
+                size: 4,
+            },
+        ];
+        data.register_result(ShaderLanguage::SPV, vertex);
+        let with_attributes = Config {
+            vertex_attributes: true,
+            ..config()
+        };
+
+        let rendered = data.render(&with_attributes).unwrap();
+        assert!(rendered.contains(
+            "    pub const TRIANGLE_VERT_VERTEX_ATTRIBUTES: &[(u32, ::wgpu::VertexFormat)] = &[\n        \
+                 (0, ::wgpu::VertexFormat::Float32x3),\n        \
+                 (1, ::wgpu::VertexFormat::Uint32),\n    \
+             ];\n"
+        ));
+        assert!(!data
+            .render(&config())
+            .unwrap()
+            .contains("_VERTEX_ATTRIBUTES"));
+    }
+
+    #[test]
+    fn empty_wrapper_module_is_an_error() {
+        let wrapped = Config {
+            generated_module: Some("shaders".to_string()),
+            ..config()
+        };
+        let rendered = CodegenData::default().render(&wrapped);
+        if cfg!(feature = "runtime-index") {
+            // the index is emitted even without any shaders
+            assert!(rendered.unwrap().contains("pub mod shaders {"));
+        } else {
+            assert!(matches!(
+                rendered,
+                Err(CodegenError::EmptyModule(module)) if module == "shaders"
+            ));
+        }
+        assert!(CodegenData::default().render(&config()).is_ok());
+    }
+
+    #[test]
+    fn appending_requires_no_index_features() {
+        let root = TempDir::new("starch-append-index").unwrap();
+        let config = Config {
+            generated: root.join("lib.rs"),
+            generated_append: true,
+            ..config()
+        };
+        let result = CodegenData::default().check_sources(&config);
+        match INDEX_FEATURE {
+            Some(feature) => assert!(matches!(
+                result,
+                Err(CodegenError::AppendUnsupported(it)) if it == feature
+            )),
+            None => assert!(result.is_ok()),
+        }
+    }
+
+    #[test]
+    fn files_are_iterable() {
+        let mut data = CodegenData::default();
+        data.register_source(
+            ShaderLanguage::WGSL,
+            file(ShaderLanguage::WGSL, &["triangle.wgsl"], None),
+        );
+        data.register_result(
+            ShaderLanguage::SPV,
+            file(ShaderLanguage::SPV, &["gen", "spv", "triangle.spv"], None),
+        );
+        data.register_result(
+            ShaderLanguage::GLSL,
+            file(
+                ShaderLanguage::GLSL,
+                &["gen", "glsl", "triangle.vert.glsl"],
+                Some(ShaderStage::Vertex),
+            ),
+        );
+
+        let sources: Vec<_> = data.iter_sources().collect();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].0, ShaderLanguage::WGSL);
+        let results: Vec<ShaderLanguage> =
+            data.iter_results().map(|(lang, _)| lang).collect();
+        assert_eq!(results, [ShaderLanguage::GLSL, ShaderLanguage::SPV]);
+    }
+
+    #[test]
+    fn indentation_is_configurable() {
+        let mut data = CodegenData::default();
+        data.register_result(
+            ShaderLanguage::GLSL,
+            file(
+                ShaderLanguage::GLSL,
+                &["gen", "glsl", "post", "blur.frag.glsl"],
+                None,
+            ),
+        );
+        let tabs = Config {
+            indent: "\t".to_string(),
+            generated_module: Some("shaders".to_string()),
+            ..config()
+        };
+
+        let rendered = data.render(&tabs).unwrap();
+        assert!(rendered
+            .contains("\n\tpub mod glsl {\n\t\tpub mod post {\n\t\t\tpub static BLUR"));
+        assert!(!rendered.contains("    "));
+    }
+
+    #[test]
+    fn language_modules_can_be_split() {
+        let mut data = CodegenData::default();
+        data.register_result(
+            ShaderLanguage::GLSL,
+            file(
+                ShaderLanguage::GLSL,
+                &["gen", "glsl", "post", "blur.frag.glsl"],
+                None,
+            ),
+        );
+
+        let entries: &Entries = &[(ShaderLanguage::GLSL, "POST_BLUR", "post::BLUR")];
+        let (root, files) = data.render_files(&config(), true).unwrap();
+        assert_eq!(
+            root,
+            format!(
+                "// GENERATED SOURCE FILE. DO NOT EDIT.\n\
+                 \n\
+                 pub mod glsl {{\n\
+                 \x20   include!(\"glsl.rs\");\n\
+                 }}\n{}",
+                indices(entries)
+            )
+        );
+        assert_eq!(
+            files,
+            vec![(
+                ShaderLanguage::GLSL,
+                format!(
+                    "// GENERATED SOURCE FILE. DO NOT EDIT.\n\
+                     pub mod post {{\n\
+                     \x20   pub static BLUR: &'static str = include_str!(\"gen/glsl/post/blur.frag.glsl\");\n\
+                     }}\n{}",
+                    phf_map(ShaderLanguage::GLSL, entries, 0)
+                )
+            )]
+        );
+    }
+
+    #[test]
+    fn stale_sources_are_detected() {
+        let root = TempDir::new("starch-check-sources").unwrap();
+        let config = Config {
+            generated: root.join("lib.rs"),
+            ..config()
+        };
+        let data = || {
+            let mut data = CodegenData::default();
+            data.register_source(
+                ShaderLanguage::WGSL,
+                file(ShaderLanguage::WGSL, &["triangle.wgsl"], None),
+            );
+            data
+        };
+
+        assert_eq!(
+            data().check_sources(&config).unwrap(),
+            vec![root.join("lib.rs")]
+        );
+        data().generate_sources(&config).unwrap();
+        assert!(data().check_sources(&config).unwrap().is_empty());
+        std::fs::write(root.join("lib.rs"), "// edited").unwrap();
+        assert_eq!(
+            data().check_sources(&config).unwrap(),
+            vec![root.join("lib.rs")]
+        );
+
+        // sources that can't be rendered aren't stale
+        let mut colliding = data();
+        for path in [["a.wgsl"], ["b.wgsl"]] {
+            let mut source = file(ShaderLanguage::WGSL, &path, None);
+            source.name = Some("same".to_string());
+            colliding.register_source(ShaderLanguage::WGSL, source);
+        }
+        assert!(matches!(
+            colliding.check_sources(&config),
+            Err(CodegenError::IdentifierCollision { .. })
+        ));
+    }
+
+    #[test]
+    fn qualified_names_colliding_with_other_names_are_rejected() {
+        let mut data = CodegenData::default();
+        for path in [
+            &["a", "blur.wgsl"][..],
+            &["b", "blur.wgsl"],
+            &["a_blur.wgsl"],
+        ] {
+            data.register_source(
+                ShaderLanguage::WGSL,
+                file(ShaderLanguage::WGSL, path, None),
+            );
+        }
+        let flat = Config {
+            nested_modules: false,
+            ..config()
+        };
+
+        match data.render(&flat) {
+            Err(CodegenError::IdentifierCollision { name, paths }) => {
+                assert_eq!(name, "A_BLUR");
+                assert_eq!(paths.len(), 2);
+            }
+            other => panic!("expected identifier collision, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+    fn colliding_index_ids_are_rejected() {
+        let mut data = CodegenData::default();
+        for path in [&["a_b", "c.wgsl"][..], &["a", "b", "c.wgsl"]] {
+            data.register_source(
+                ShaderLanguage::WGSL,
+                file(ShaderLanguage::WGSL, path, None),
+            );
+        }
+
+        match data.render(&config()) {
+            Err(CodegenError::IdentifierCollision { name, paths }) => {
+                assert_eq!(name, "A_B_C");
+                assert_eq!(
+                    paths,
+                    vec![PathBuf::from("a/b/c.wgsl"), PathBuf::from("a_b/c.wgsl")]
+                );
+            }
+            other => panic!("expected identifier collision, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn compressed_outputs_get_decompress_fn() {
+        let mut data = CodegenData::default();
+        let mut output = file(
+            ShaderLanguage::SPV,
+            &["gen", "spv", "triangle.vert.spv.gz"],
+            Some(ShaderStage::Vertex),
+        );
+        output.compressed = true;
+        data.register_result(ShaderLanguage::SPV, output);
+
+        let rendered = data.render(&config()).unwrap();
+        assert!(rendered.contains(
+            "pub static TRIANGLE_VERT: &'static [u8] = \
+             include_bytes!(\"gen/spv/triangle.vert.spv.gz\");"
+        ));
+        assert!(rendered.contains("pub fn TRIANGLE_VERT_decompressed() -> Vec<u8> {"));
+        assert!(rendered.contains("::flate2::read::GzDecoder::new(TRIANGLE_VERT)"));
+    }
+
+    #[test]
+    fn out_dir_outputs_are_included_from_env() {
+        let config = Config {
+            include_path_mode: IncludePathMode::OutDir,
+            ..config()
+        };
+        let mut data = CodegenData::default();
+        data.register_source(
+            ShaderLanguage::WGSL,
+            file(ShaderLanguage::WGSL, &["triangle.wgsl"], None),
+        );
+        let mut output = file(
+            ShaderLanguage::GLSL,
+            &["triangle.vert.glsl"],
+            Some(ShaderStage::Vertex),
+        );
+        output.path = config
+            .target_include_path(ShaderLanguage::GLSL)
+            .join("triangle.vert.glsl");
+        data.register_result(ShaderLanguage::GLSL, output);
+
+        let rendered = data.render(&config).unwrap();
+        assert!(rendered.contains("include_str!(\"triangle.wgsl\");"));
+        assert!(rendered.contains(
+            "pub static TRIANGLE_VERT: &'static str = \
+             include_str!(concat!(env!(\"OUT_DIR\"), \"/gen/glsl/triangle.vert.glsl\"));"
+        ));
+    }
+
+    #[test]
+    fn manifest_maps_sources_to_outputs() {
+        let mut data = CodegenData::default();
+        data.register_source(
+            ShaderLanguage::WGSL,
+            file(ShaderLanguage::WGSL, &["triangle.wgsl"], None),
+        );
+        let mut output = file(
+            ShaderLanguage::GLSL,
+            &["gen", "glsl", "triangle.vert.glsl"],
+            Some(ShaderStage::Vertex),
+        );
+        output.source = Some(PathBuf::from("triangle.wgsl"));
+        data.register_result(ShaderLanguage::GLSL, output);
+        let mut output = file(
+            ShaderLanguage::SPV,
+            &["gen", "spv", "triangle.vert.spv"],
+            Some(ShaderStage::Vertex),
+        );
+        output.source = Some(PathBuf::from("triangle.wgsl"));
+        output.listing = Some(PathBuf::from("gen/spv/triangle.vert.spvasm"));
+        data.register_result(ShaderLanguage::SPV, output);
+
+        let expected = r#"{
+  "sources": [
+    {
+      "path": "triangle.wgsl",
+      "language": "wgsl",
+      "outputs": [
+        { "path": "gen/glsl/triangle.vert.glsl", "language": "glsl", "stage": "vertex" },
+        { "path": "gen/spv/triangle.vert.spv", "language": "spv", "stage": "vertex", "listing": "gen/spv/triangle.vert.spvasm" }
+      ]
+    }
+  ]
+}
+"#;
+        assert_eq!(data.render_manifest(), expected);
+        assert_eq!(json_string("a\"b\\c\n"), r#""a\"b\\c\u000a""#);
+    }
+}
+
+#[cfg(all(test, feature = "phf"))]
+mod phf_tests {
+    use super::*;
+
+    #[test]
+    fn phf_map_lists_language_entries() {
+        let entries = vec![
+            IndexEntry {
+                lang: ShaderLanguage::GLSL,
+                #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                id: "POST_BLUR_FRAG".to_string(),
+                #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                file: PathBuf::from("gen/glsl/post/blur.frag.glsl"),
+                path: "post::BLUR_FRAG".to_string(),
+                #[cfg(feature = "generate-tests")]
+                compressed: false,
+            },
+            IndexEntry {
+                lang: ShaderLanguage::SPV,
+                #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                id: "TRIANGLE".to_string(),
+                #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                file: PathBuf::from("gen/spv/triangle.spv"),
+                path: "TRIANGLE".to_string(),
+                #[cfg(feature = "generate-tests")]
+                compressed: false,
+            },
+        ];
+        let c = Context {
+            indent: 1,
+            visibility: "pub".to_string(),
+            stage_constants: false,
+            vertex_attributes: false,
+        };
+
+        assert_eq!(
+            format_phf_map(ShaderLanguage::GLSL, &entries, &c),
+            "    pub static MAP: ::phf::Map<&'static str, &'static [u8]> = ::phf::phf_map! {\n\
+             \x20       \"post/blur_frag\" => post::BLUR_FRAG.as_bytes(),\n\
+             \x20   };\n"
+        );
+        assert!(format_phf_map(ShaderLanguage::SPV, &entries, &c)
+            .contains("\"triangle\" => TRIANGLE,"));
+    }
+}
+
+#[cfg(all(test, feature = "generate-tests"))]
+mod generated_tests_tests {
+    use super::*;
+
+    #[test]
+    fn outputs_get_validity_tests() {
+        let entries = vec![
+            IndexEntry {
+                lang: ShaderLanguage::WGSL,
+                #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                id: "TRIANGLE".to_string(),
+                #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                file: PathBuf::from("gen/wgsl/triangle.wgsl"),
+                path: "TRIANGLE".to_string(),
+                #[cfg(feature = "generate-tests")]
+                compressed: false,
+            },
+            IndexEntry {
+                lang: ShaderLanguage::SPV,
+                #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                id: "POST_BLUR_COMP".to_string(),
+                #[cfg(any(feature = "runtime-index", feature = "generate-tests"))]
+                file: PathBuf::from("gen/spv/post/blur.comp.spv.gz"),
+                path: "post::BLUR_COMP".to_string(),
+                #[cfg(feature = "generate-tests")]
+                compressed: true,
+            },
+        ];
+
+        let tests = format_generated_tests(&entries);
+        assert!(tests.starts_with("\n#[cfg(test)]\nmod starch_tests {\n"));
+        assert!(tests.contains(
+            "    fn wgsl_triangle_is_valid() {\n\
+             \x20       assert!(!super::wgsl::TRIANGLE.is_empty());\n\
+             \x20   }\n"
+        ));
+        assert!(tests.contains(
+            "    fn spv_post_blur_comp_is_valid() {\n\
+             \x20       let bytes: &[u8] = &super::spv::post::BLUR_COMP_decompressed();\n\
+             \x20       assert_eq!(bytes.len() % 4, 0, \"not made of 32-bit words\");\n"
+        ));
+        assert!(format_generated_tests(&[]).is_empty());
     }
 }
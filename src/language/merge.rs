@@ -0,0 +1,404 @@
+//! Merging of generated sources into the starch managed region of an existing
+//! file. Only understands the subset of Rust starch itself emits.
+
+use crate::config::OUT_DIR_INCLUDE;
+
+const REGION_BEGIN: &str = "// starch:begin";
+const REGION_END: &str = "// starch:end";
+
+/// Suffixes of items emitted alongside a shader static, named after it.
+const DERIVED_SUFFIXES: &[&str] = &[
+    "",
+    "_decompressed",
+    "_STAGE",
+    "_WORKGROUP_SIZE",
+    "_VERTEX_ATTRIBUTES",
+];
+
+#[derive(Debug)]
+enum Entry<'a> {
+    Item(String),
+    Module {
+        open: String,
+        items: Items<'a>,
+        close: &'a str,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Items<'a> {
+    entries: Vec<(String, Entry<'a>)>,
+    trailing: String,
+}
+
+/// Places `rendered` into the managed region of `existing`, merging it with
+/// items already in the region. Items `rendered` redefines are replaced. Statics
+/// of the region including a file `is_missing` reports as gone are dropped,
+/// along with items derived from them. The region is appended if `existing`
+/// doesn't have one.
+pub(crate) fn merge_region(
+    existing: &str,
+    rendered: &str,
+    is_missing: &dyn Fn(&str) -> bool,
+) -> String {
+    let mut result = String::with_capacity(existing.len() + rendered.len());
+    let (region, rest) = match find_region(existing) {
+        Some((begin, region, end)) => {
+            result.push_str(&existing[..begin]);
+            (region, &existing[end..])
+        }
+        None if existing.is_empty() => ("", ""),
+        None => {
+            result.push_str(existing.trim_end_matches('\n'));
+            result.push_str("\n\n");
+            ("", "")
+        }
+    };
+
+    let (_, old) = split_preamble(region);
+    let (preamble, new) = split_preamble(rendered);
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let mut items = parse_items(&old_lines, &mut 0);
+    prune_items(&mut items, is_missing);
+    merge_items(&mut items, parse_items(&new_lines, &mut 0));
+
+    result.push_str(REGION_BEGIN);
+    result.push('\n');
+    result.push_str(preamble);
+    write_items(&items, &mut result);
+    result.push_str(REGION_END);
+    result.push('\n');
+    result.push_str(rest);
+    result
+}
+
+/// `source` with its managed region removed, or `None` if it doesn't have one.
+pub(crate) fn strip_region(source: &str) -> Option<String> {
+    let (begin, _, end) = find_region(source)?;
+    let before = source[..begin].trim_end_matches('\n');
+    let rest = source[end..].trim_start_matches('\n');
+    Some(match (before.is_empty(), rest.is_empty()) {
+        (true, _) => rest.to_string(),
+        (false, true) => format!("{}\n", before),
+        (false, false) => format!("{}\n\n{}", before, rest),
+    })
+}
+
+/// Byte offsets of the region start, its contents and the end of the region in
+/// `source`.
+fn find_region(source: &str) -> Option<(usize, &str, usize)> {
+    let mut offset = 0;
+    let mut begin = None;
+    for line in source.split_inclusive('\n') {
+        let next = offset + line.len();
+        match (line.trim(), begin) {
+            (REGION_BEGIN, None) => begin = Some((offset, next)),
+            (REGION_END, Some((start, contents))) => {
+                return Some((start, &source[contents..offset], next));
+            }
+            _ => {}
+        }
+        offset = next;
+    }
+    None
+}
+
+/// Splits leading comments, like the generated banner, from the rest.
+fn split_preamble(source: &str) -> (&str, &str) {
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if !trimmed.starts_with("//") || trimmed.starts_with("///") {
+            break;
+        }
+        offset += line.len();
+    }
+    source.split_at(offset)
+}
+
+fn parse_items<'a>(lines: &[&'a str], i: &mut usize) -> Items<'a> {
+    let mut items = Items::default();
+    let mut pending = String::new();
+    while let Some(line) = lines.get(*i) {
+        let trimmed = line.trim();
+        if trimmed == "}" {
+            break;
+        }
+        pending.push_str(line);
+        pending.push('\n');
+        *i += 1;
+
+        if trimmed.is_empty() || trimmed.starts_with("//") || trimmed.starts_with("#[") {
+            continue;
+        }
+        if let Some(name) = module_name(trimmed) {
+            let module = parse_items(lines, i);
+            let close = lines.get(*i).copied().unwrap_or("}");
+            *i += 1;
+            items.entries.push((
+                format!("mod {}", name),
+                Entry::Module {
+                    open: std::mem::take(&mut pending),
+                    items: module,
+                    close,
+                },
+            ));
+            continue;
+        }
+
+        let key = item_key(trimmed);
+        let mut depth = brace_depth(trimmed);
+        while depth > 0 {
+            let line = match lines.get(*i) {
+                Some(line) => line,
+                None => break,
+            };
+            pending.push_str(line);
+            pending.push('\n');
+            depth += brace_depth(line);
+            *i += 1;
+        }
+        items
+            .entries
+            .push((key, Entry::Item(std::mem::take(&mut pending))));
+    }
+    items.trailing = pending;
+    items
+}
+
+fn merge_items<'a>(items: &mut Items<'a>, new: Items<'a>) {
+    for (key, entry) in new.entries {
+        match items.entries.iter_mut().find(|(it, _)| *it == key) {
+            Some((_, old)) => match (old, entry) {
+                (
+                    Entry::Module { open, items, close },
+                    Entry::Module {
+                        open: new_open,
+                        items: new_items,
+                        close: new_close,
+                    },
+                ) => {
+                    *open = new_open;
+                    *close = new_close;
+                    merge_items(items, new_items);
+                }
+                (old, entry) => *old = entry,
+            },
+            None => items.entries.push((key, entry)),
+        }
+    }
+    items.trailing = new.trailing;
+}
+
+/// Drops statics including missing files, along with items derived from them.
+fn prune_items(items: &mut Items, is_missing: &dyn Fn(&str) -> bool) {
+    let stale: Vec<String> = items
+        .entries
+        .iter()
+        .filter_map(|(key, entry)| match entry {
+            Entry::Item(text) => {
+                let name = key.strip_prefix("static ")?;
+                included_path(text)
+                    .filter(|path| is_missing(path))
+                    .map(|_| name.to_string())
+            }
+            Entry::Module { .. } => None,
+        })
+        .collect();
+    items.entries.retain(|(key, _)| {
+        let name = key.split_whitespace().last().unwrap_or(key);
+        !stale.iter().any(|stale| {
+            DERIVED_SUFFIXES
+                .iter()
+                .any(|suffix| name.strip_prefix(stale.as_str()) == Some(suffix))
+        })
+    });
+    for (_, entry) in &mut items.entries {
+        if let Entry::Module { items, .. } = entry {
+            prune_items(items, is_missing);
+        }
+    }
+}
+
+/// Path a static includes, as `Config::out_include_path` would return it.
+fn included_path(text: &str) -> Option<String> {
+    let (_, arg) = text
+        .split_once("include_str!(")
+        .or_else(|| text.split_once("include_bytes!("))?;
+    let arg = arg.trim_end().strip_suffix(");")?;
+    match arg
+        .strip_prefix("concat!(env!(\"OUT_DIR\"), ")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        Some(rest) => Some(format!("{}{}", OUT_DIR_INCLUDE, rest.trim_matches('"'))),
+        None => Some(arg.trim_matches('"').to_string()),
+    }
+}
+
+fn write_items(items: &Items, result: &mut String) {
+    for (_, entry) in &items.entries {
+        match entry {
+            Entry::Item(text) => result.push_str(text),
+            Entry::Module { open, items, close } => {
+                result.push_str(open);
+                write_items(items, result);
+                result.push_str(close);
+                result.push('\n');
+            }
+        }
+    }
+    result.push_str(&items.trailing);
+}
+
+/// Name of the module `line` opens, if it opens one.
+fn module_name(line: &str) -> Option<&str> {
+    let words: Vec<&str> = line.strip_suffix('{')?.split_whitespace().collect();
+    match words.as_slice() {
+        [.., "mod", name] => Some(name),
+        _ => None,
+    }
+}
+
+/// Identifies an item by its kind and name, e.g. `static TRIANGLE_VERT`.
+fn item_key(line: &str) -> String {
+    let mut words = line
+        .split_whitespace()
+        .skip_while(|word| word.starts_with("pub"));
+    match (words.next(), words.next()) {
+        (Some(kind), Some(name)) => {
+            let name = name
+                .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .next()
+                .unwrap_or(name);
+            format!("{} {}", kind, name)
+        }
+        _ => line.to_string(),
+    }
+}
+
+/// Change of brace nesting over `line`, ignoring braces in string literals.
+fn brace_depth(line: &str) -> isize {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if in_string => {
+                chars.next();
+            }
+            '"' => in_string = !in_string,
+            '{' if !in_string => depth += 1,
+            '}' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statics_are_merged_into_language_modules() {
+        let existing = "use std::fmt;\n\
+            \n\
+            // starch:begin\n\
+            // GENERATED SOURCE FILE. DO NOT EDIT.\n\
+            \n\
+            pub mod glsl {\n\
+            \x20   pub static A: &'static str = include_str!(\"a{.glsl\");\n\
+            \x20   pub static B: &'static str = include_str!(\"old/b.glsl\");\n\
+            }\n\
+            // starch:end\n\
+            \n\
+            fn hand_written() {}\n";
+        let rendered = "// GENERATED SOURCE FILE. DO NOT EDIT.\n\
+            \n\
+            pub mod glsl {\n\
+            \x20   pub static B: &'static str = include_str!(\"b.glsl\");\n\
+            \x20   pub mod post {\n\
+            \x20       pub static C: &'static str = include_str!(\"post/c.glsl\");\n\
+            \x20   }\n\
+            }\n\
+            \n\
+            pub mod spv {\n\
+            \x20   pub static D: &'static [u8] = include_bytes!(\"d.spv\");\n\
+            }\n";
+
+        let merged = merge_region(existing, rendered, &|_| false);
+        assert_eq!(
+            merged,
+            "use std::fmt;\n\
+            \n\
+            // starch:begin\n\
+            // GENERATED SOURCE FILE. DO NOT EDIT.\n\
+            \n\
+            pub mod glsl {\n\
+            \x20   pub static A: &'static str = include_str!(\"a{.glsl\");\n\
+            \x20   pub static B: &'static str = include_str!(\"b.glsl\");\n\
+            \x20   pub mod post {\n\
+            \x20       pub static C: &'static str = include_str!(\"post/c.glsl\");\n\
+            \x20   }\n\
+            }\n\
+            \n\
+            pub mod spv {\n\
+            \x20   pub static D: &'static [u8] = include_bytes!(\"d.spv\");\n\
+            }\n\
+            // starch:end\n\
+            \n\
+            fn hand_written() {}\n"
+        );
+        assert_eq!(merge_region(&merged, rendered, &|_| false), merged);
+    }
+
+    #[test]
+    fn statics_of_missing_files_are_pruned() {
+        let existing = "// starch:begin\n\
+            pub mod spv {\n\
+            \x20   pub static A_VERT: &'static [u8] = include_bytes!(\"a.vert.spv\");\n\
+            \x20   pub const A_VERT_STAGE: ::naga::ShaderStage = ::naga::ShaderStage::Vertex;\n\
+            \x20   pub static A_VERT_B: &'static [u8] = include_bytes!(concat!(env!(\"OUT_DIR\"), \"/b.spv\"));\n\
+            }\n\
+            // starch:end\n";
+        let rendered = "pub mod spv {\n}\n";
+
+        let merged = merge_region(existing, rendered, &|path| path == "a.vert.spv");
+        assert_eq!(
+            merged,
+            "// starch:begin\n\
+            pub mod spv {\n\
+            \x20   pub static A_VERT_B: &'static [u8] = include_bytes!(concat!(env!(\"OUT_DIR\"), \"/b.spv\"));\n\
+            }\n\
+            // starch:end\n"
+        );
+        let merged = merge_region(existing, rendered, &|path| path == "$OUT_DIR/b.spv");
+        assert!(merged.contains("A_VERT_STAGE"));
+        assert!(!merged.contains("A_VERT_B"));
+    }
+
+    #[test]
+    fn region_is_stripped() {
+        let merged = merge_region("mod other;", "pub mod wgsl {\n}\n", &|_| false);
+        assert_eq!(strip_region(&merged).unwrap(), "mod other;\n");
+        assert_eq!(
+            strip_region("// starch:begin\n// starch:end\nfn main() {}\n").unwrap(),
+            "fn main() {}\n"
+        );
+        assert_eq!(strip_region("mod other;\n"), None);
+    }
+
+    #[test]
+    fn region_is_appended_to_files_without_one() {
+        let rendered = "pub mod wgsl {\n}\n";
+        assert_eq!(
+            merge_region("mod other;", rendered, &|_| false),
+            "mod other;\n\n// starch:begin\npub mod wgsl {\n}\n// starch:end\n"
+        );
+        assert_eq!(
+            merge_region("", rendered, &|_| false),
+            "// starch:begin\npub mod wgsl {\n}\n// starch:end\n"
+        );
+    }
+}
@@ -1,2 +1,5 @@
+#[cfg(all(feature = "wgsl-in", feature = "wgsl-out"))]
+pub mod bundle;
 pub mod codegen;
+pub(crate) mod merge;
 pub mod transpile;
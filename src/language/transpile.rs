@@ -1,17 +1,26 @@
 use crate::config::Config;
-use crate::error::{SourceError, TranspileError, VecErr};
-use crate::language::codegen::CodegenData;
-use crate::shader::{Shader, ShaderCode};
+use crate::error::{ParseLanguageError, SourceError, TranspileError};
+use crate::language::codegen::{CodegenData, MANIFEST_FILE_NAME};
+use crate::layout::{vertex_attributes, VertexAttribute};
+use crate::shader::{Shader, ShaderCode, Shaders};
 #[allow(unused_imports)]
 use crate::util::LogResult;
-use crate::util::{file_prefix, Name};
-use naga::proc::BoundsCheckPolicies;
+use crate::util::{
+    collect_files, file_prefix, sanitize_identifier, stage_span, temp_path,
+    warn_feature_disabled, write_atomic, StageExt, TempDir,
+};
+use naga::valid::Validator;
 use naga::{EntryPoint, Module, ShaderStage};
 #[cfg(feature = "config-file")]
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
 use std::fmt::{Display, Formatter};
+use std::fs::File;
 use std::hash::Hash;
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
@@ -39,24 +48,34 @@ impl ShaderLanguage {
         ShaderLanguage::MSL,
     ];
 
-    #[warn(unreachable_code)]
     pub fn from_file_name(path: impl AsRef<Path>) -> Option<ShaderLanguage> {
         let ext = path
             .as_ref()
             .extension()
             .and_then(|os_str| os_str.to_str())?;
+        ShaderLanguage::from_extension(ext)
+    }
+
+    /// Language of sources with extension `ext`, with or without the leading
+    /// dot. Only languages starch can read are returned.
+    pub fn from_extension(ext: &str) -> Option<ShaderLanguage> {
+        let ext = ext.strip_prefix('.').unwrap_or(ext).to_ascii_lowercase();
+        ShaderLanguage::ALL
+            .into_iter()
+            .find(|lang| lang.extensions().contains(&ext.as_str()))
+    }
 
-        Some(match ext.to_ascii_lowercase().as_str() {
+    /// Extensions of source files that can be read as this language.
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
             #[cfg(feature = "wgsl-in")]
-            "wgsl" => ShaderLanguage::WGSL,
+            ShaderLanguage::WGSL => &["wgsl"],
             #[cfg(feature = "glsl-in")]
-            "glsl" | "vs" | "fs" | "cs" | "vert" | "frag" | "comp" => {
-                ShaderLanguage::GLSL
-            }
+            ShaderLanguage::GLSL => &["glsl", "vs", "fs", "cs", "vert", "frag", "comp"],
             #[cfg(feature = "spv-in")]
-            "spv" => ShaderLanguage::SPV,
-            _ => return None,
-        })
+            ShaderLanguage::SPV => &["spv"],
+            _ => &[],
+        }
     }
 
     pub fn to_str(&self) -> &'static str {
@@ -83,63 +102,99 @@ impl ShaderLanguage {
         *self == ShaderLanguage::SPV
     }
 
-    pub(crate) fn get_ext(&self, stage: Option<ShaderStage>) -> &'static str {
+    /// MIME type of files in the language.
+    pub fn mime_type(self) -> &'static str {
+        if self.is_binary() {
+            "application/octet-stream"
+        } else {
+            "text/plain"
+        }
+    }
+
+    /// Value of a `Content-Type` header for serving files in the language.
+    pub fn content_type(self) -> &'static str {
+        if self.is_binary() {
+            self.mime_type()
+        } else {
+            "text/plain; charset=utf-8"
+        }
+    }
+
+    pub(crate) fn output_feature(&self) -> &'static str {
         match self {
-            ShaderLanguage::WGSL => match stage {
-                Some(ShaderStage::Vertex) => "vert.wgsl",
-                Some(ShaderStage::Fragment) => "frag.wgsl",
-                Some(ShaderStage::Compute) => "comp.wgsl",
-                None => "wgsl",
-            },
-            ShaderLanguage::GLSL => match stage {
-                Some(ShaderStage::Vertex) => "vert.glsl",
-                Some(ShaderStage::Fragment) => "frag.glsl",
-                Some(ShaderStage::Compute) => "comp.glsl",
-                None => "glsl",
-            },
-            ShaderLanguage::SPV => match stage {
-                Some(ShaderStage::Vertex) => "v.spv",
-                Some(ShaderStage::Fragment) => "f.spv",
-                Some(ShaderStage::Compute) => "c.spv",
-                None => "spv",
-            },
-            ShaderLanguage::HLSL => match stage {
-                Some(ShaderStage::Vertex) => "vert.hlsl",
-                Some(ShaderStage::Fragment) => "frag.hlsl",
-                Some(ShaderStage::Compute) => "comp.hlsl",
-                None => "hlsl",
-            },
-            ShaderLanguage::MSL => match stage {
-                Some(ShaderStage::Vertex) => "vert.msl",
-                Some(ShaderStage::Fragment) => "frag.msl",
-                Some(ShaderStage::Compute) => "comp.msl",
-                None => "msl",
-            },
+            ShaderLanguage::WGSL => "wgsl-out",
+            ShaderLanguage::GLSL => "glsl-out",
+            ShaderLanguage::SPV => "spv-out",
+            ShaderLanguage::HLSL => "hlsl-out",
+            ShaderLanguage::MSL => "msl-out",
         }
     }
 
-    pub fn parse(self, shader: &mut Shader) -> Result<&Module, SourceError> {
-        if shader.module.is_some() {
-            return Ok(shader.module.as_ref().unwrap());
+    /// Whether output can only be generated for a single entry point.
+    pub(crate) fn requires_entry_point(&self) -> bool {
+        matches!(self, ShaderLanguage::GLSL | ShaderLanguage::SPV)
+    }
+
+    /// Whether this build can read or write the language.
+    pub(crate) fn enabled(&self) -> bool {
+        self.can_input() || self.can_output()
+    }
+
+    /// Whether this build can parse sources in the language.
+    pub fn can_input(self) -> bool {
+        match self {
+            ShaderLanguage::WGSL => cfg!(feature = "wgsl-in"),
+            ShaderLanguage::GLSL => cfg!(feature = "glsl-in"),
+            ShaderLanguage::SPV => cfg!(feature = "spv-in"),
+            ShaderLanguage::HLSL | ShaderLanguage::MSL => false,
+        }
+    }
+
+    /// Whether this build can generate output in the language.
+    pub fn can_output(self) -> bool {
+        match self {
+            ShaderLanguage::WGSL => cfg!(feature = "wgsl-out"),
+            ShaderLanguage::GLSL => cfg!(feature = "glsl-out"),
+            ShaderLanguage::SPV => cfg!(feature = "spv-out"),
+            ShaderLanguage::HLSL => cfg!(feature = "hlsl-out"),
+            ShaderLanguage::MSL => cfg!(feature = "msl-out"),
+        }
+    }
+
+    /// Output file extension, `stage` is `None` for outputs with entry points of
+    /// several stages.
+    pub(crate) fn get_ext(&self, stage: Option<ShaderStage>) -> String {
+        match stage {
+            // SPIR-V outputs only keep the first letter, e.g. `v.spv`
+            Some(stage) if *self == ShaderLanguage::SPV => {
+                format!("{}.{}", &stage.short_name()[..1], self.to_str())
+            }
+            Some(stage) => format!("{}.{}", stage.short_name(), self.to_str()),
+            None => self.to_str().to_string(),
         }
+    }
 
-        shader.module = Some({
+    pub fn parse(self, shader: &mut Shader) -> Result<&Module, SourceError> {
+        if shader.module.is_none() {
+            #[cfg(any(feature = "spv-in", feature = "wgsl-in", feature = "glsl-in"))]
             let source = shader.source.as_ref().expect("no shader source");
 
-            match self {
+            let module: Result<Module, SourceError> = match self {
                 #[cfg(feature = "spv-in")]
                 ShaderLanguage::SPV => {
                     use naga::front::spv;
 
                     let options = spv::Options::default();
-                    spv::parse_u8_slice(source.unwrap_binary(), &options)?
+                    spv::parse_u8_slice(source.unwrap_binary(), &options)
+                        .map_err(Into::into)
                 }
                 #[cfg(feature = "wgsl-in")]
                 ShaderLanguage::WGSL => {
-                    naga::front::wgsl::parse_str(source.unwrap_text())?
+                    naga::front::wgsl::parse_str(source.unwrap_text()).map_err(Into::into)
                 }
                 #[cfg(feature = "glsl-in")]
                 ShaderLanguage::GLSL => {
+                    use crate::error::VecErr;
                     use naga::front::glsl;
 
                     let stage = shader
@@ -147,49 +202,80 @@ impl ShaderLanguage {
                         .ok_or(SourceError::UnhandledShaderStage)?;
                     let options = glsl::Options {
                         stage,
-                        defines: Default::default(),
+                        defines: shader
+                            .defines
+                            .iter()
+                            .map(|(name, value)| (name.clone(), value.clone()))
+                            .collect(),
                     };
 
                     let mut parser = glsl::Parser::default();
 
                     parser
                         .parse(&options, source.unwrap_text())
-                        .map_err(|e| VecErr::from(e))?
+                        .map_err(|err| VecErr::from(err).into())
                 }
-                _ => unimplemented!("parse target not implemented"),
-            }
-        });
+                _ => Err(SourceError::NotSupported(self)),
+            };
+            shader.module = Some(module?);
+        }
 
         Ok(shader.module.as_ref().expect("no module after parsing"))
     }
 
-    #[allow(unreachable_patterns)]
-    pub fn generate<'a>(
+    pub fn generate(
         self,
         shader: &Shader,
         result: &mut ShaderCode,
         target: Option<&EntryPoint>,
-    ) -> Result<(), TranspileError<'a>> {
-        match self {
+    ) -> Result<(), TranspileError> {
+        self.generate_to(shader, result, target).map(|_| ())
+    }
+
+    /// Generates output straight into `writer`, without buffering it whole.
+    /// SPIR-V is the exception: naga's writer only fills a `Vec<u32>`, so the
+    /// module is collected before it's written out. Returns names the backend
+    /// reported for renamed identifiers.
+    #[cfg(any(
+        feature = "spv-out",
+        feature = "glsl-out",
+        feature = "wgsl-out",
+        feature = "hlsl-out",
+        feature = "msl-out"
+    ))]
+    pub fn generate_to<W: std::io::Write>(
+        self,
+        shader: &Shader,
+        writer: &mut W,
+        target: Option<&EntryPoint>,
+    ) -> Result<NameMap, TranspileError> {
+        // only the SPIR-V and GLSL backends write a single entry point
+        #[cfg(not(any(feature = "spv-out", feature = "glsl-out")))]
+        let _ = target;
+
+        let mut result = FmtWriter::new(writer);
+        let names = match self {
             #[cfg(feature = "spv-out")]
             ShaderLanguage::SPV => {
-                use byteorder::{WriteBytesExt, LE};
                 use naga::back::spv;
 
                 let target = target.ok_or(TranspileError::NoEntryPoint)?;
+                if target.name.is_empty() && shader.entry_point_name.is_none() {
+                    return Err(TranspileError::NoEntryPoint);
+                }
 
-                let options = spv::Options::default();
+                let options = spv::Options {
+                    bounds_check_policies: shader.bounds_check.policies(),
+                    ..Default::default()
+                };
                 let mut writer = spv::Writer::new(&options)?;
 
                 let pipeline_options = spv::PipelineOptions {
                     shader_stage: target.stage,
-                    entry_point: target
-                        .function
-                        .name
-                        .clone()
-                        .ok_or(TranspileError::NoEntryPoint)?,
+                    entry_point: target.name.clone(),
                 };
 
+                // naga 0.9 can't write SPIR-V into anything but a Vec
                 let mut words: Vec<u32> = vec![];
                 writer.write(
                     shader.module.as_ref().expect("no module"),
@@ -197,9 +283,11 @@ impl ShaderLanguage {
                     Some(&pipeline_options),
                     &mut words,
                 )?;
-                for w in words {
-                    result.write_u32::<LE>(w)?;
+                if let Some(name) = &shader.entry_point_name {
+                    rename_spv_entry_points(&mut words, name);
                 }
+                result.write_words(words)?;
+                NameMap::default()
             }
             #[cfg(feature = "glsl-out")]
             ShaderLanguage::GLSL => {
@@ -208,75 +296,216 @@ impl ShaderLanguage {
                 let target = target.ok_or(TranspileError::NoEntryPoint)?;
 
                 #[cfg(not(feature = "web-glsl-out"))]
-                let options = glsl::Options {
-                    version: glsl::Version::Desktop(430),
-                    ..Default::default()
-                };
+                let version = glsl::Version::Desktop(430);
                 #[cfg(feature = "web-glsl-out")]
+                let version = glsl::Version::Embedded {
+                    version: 300,
+                    is_webgl: true,
+                };
                 let options = glsl::Options {
-                    version: glsl::Version::Embedded {
-                        version: 300,
-                        is_webgl: true,
-                    },
-                    ..Default::default()
+                    version,
+                    writer_flags: shader.glsl.writer_flags(),
+                    binding_map: shader.glsl.binding_map(),
                 };
 
                 let pipeline_options = glsl::PipelineOptions {
                     shader_stage: target.stage,
-                    entry_point: target
-                        .function
-                        .name
-                        .clone()
-                        .ok_or(TranspileError::NoEntryPoint)?,
-                    multiview: None,
+                    entry_point: target.name.clone(),
+                    multiview: shader.glsl.multiview,
                 };
 
                 let mut writer = glsl::Writer::new(
-                    result,
+                    &mut result,
                     shader.module.as_ref().expect("no module"),
                     shader.module_info.as_ref().expect("no module info"),
                     &options,
                     &pipeline_options,
-                    BoundsCheckPolicies::default(),
+                    shader.bounds_check.policies(),
                 )?;
-                writer.write()?;
+                let reflection = writer.write()?;
+
+                let module = shader.module.as_ref().expect("no module");
+                let mut names = NameMap::default();
+                for (handle, name) in reflection.uniforms {
+                    names.globals.insert(global_name(module, handle), name);
+                }
+                for (name, mapping) in reflection.texture_mapping {
+                    names.textures.insert(
+                        name,
+                        (
+                            global_name(module, mapping.texture),
+                            mapping.sampler.map(|sampler| global_name(module, sampler)),
+                        ),
+                    );
+                }
+                names
             }
             #[cfg(feature = "wgsl-out")]
             ShaderLanguage::WGSL => {
                 use naga::back::wgsl;
 
-                let mut writer = wgsl::Writer::new(result, wgsl::WriterFlags::empty());
+                let mut writer =
+                    wgsl::Writer::new(&mut result, wgsl::WriterFlags::empty());
                 writer.write(
                     shader.module.as_ref().expect("no module"),
                     shader.module_info.as_ref().expect("no module info"),
                 )?;
+                NameMap::default()
             }
             #[cfg(feature = "hlsl-out")]
             ShaderLanguage::HLSL => {
                 use naga::back::hlsl;
 
-                let mut writer = hlsl::Writer::new(result, &hlsl::Options::default());
-                writer.write(
-                    shader.module.as_ref().expect("no module"),
+                let options = hlsl::Options::default();
+                let mut writer = hlsl::Writer::new(&mut result, &options);
+                let module = shader.module.as_ref().expect("no module");
+                let reflection = writer.write(
+                    module,
                     shader.module_info.as_ref().expect("no module info"),
                 )?;
+                let mut names = NameMap::default();
+                for (entry_point, name) in
+                    module.entry_points.iter().zip(reflection.entry_point_names)
+                {
+                    if let Ok(name) = name {
+                        names.entry_points.insert(entry_point.name.clone(), name);
+                    }
+                }
+                names
             }
             #[cfg(feature = "msl-out")]
             ShaderLanguage::MSL => {
                 use naga::back::msl;
 
-                let mut writer = msl::Writer::new(result);
+                let mut writer = msl::Writer::new(&mut result);
                 writer.write(
                     shader.module.as_ref().expect("no module"),
                     shader.module_info.as_ref().expect("no module info"),
-                    &msl::Options::default(),
+                    &msl::Options {
+                        bounds_check_policies: shader.bounds_check.policies(),
+                        ..Default::default()
+                    },
                     &msl::PipelineOptions::default(),
                 )?;
+                NameMap::default()
             }
+            #[cfg(not(all(
+                feature = "spv-out",
+                feature = "glsl-out",
+                feature = "wgsl-out",
+                feature = "hlsl-out",
+                feature = "msl-out"
+            )))]
             _ => return Err(TranspileError::TargetNotSupported),
+        };
+        result.finish()?;
+        Ok(names)
+    }
+
+    /// Always fails, starch was built without any output features.
+    #[cfg(not(any(
+        feature = "spv-out",
+        feature = "glsl-out",
+        feature = "wgsl-out",
+        feature = "hlsl-out",
+        feature = "msl-out"
+    )))]
+    pub fn generate_to<W: std::io::Write>(
+        self,
+        _shader: &Shader,
+        _writer: &mut W,
+        _target: Option<&EntryPoint>,
+    ) -> Result<NameMap, TranspileError> {
+        Err(TranspileError::TargetNotSupported)
+    }
+}
+
+/// Names identifiers ended up with in generated code, as far as the backend
+/// reports them. Keys are names from the source module.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NameMap {
+    /// Uniform and storage globals, GLSL only.
+    pub globals: BTreeMap<String, String>,
+    /// Entry points, HLSL only.
+    pub entry_points: BTreeMap<String, String>,
+    /// GLSL combined samplers, mapped to the texture and sampler they're made of.
+    pub textures: BTreeMap<String, (String, Option<String>)>,
+}
+
+impl NameMap {
+    pub fn is_empty(&self) -> bool {
+        self.globals.is_empty()
+            && self.entry_points.is_empty()
+            && self.textures.is_empty()
+    }
+}
+
+#[cfg(feature = "glsl-out")]
+fn global_name(module: &Module, handle: naga::Handle<naga::GlobalVariable>) -> String {
+    module.global_variables[handle]
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("global{}", handle.index()))
+}
+
+/// Adapts an io writer for naga backends which expect a `fmt::Write`.
+#[cfg(any(
+    feature = "spv-out",
+    feature = "glsl-out",
+    feature = "wgsl-out",
+    feature = "hlsl-out",
+    feature = "msl-out"
+))]
+struct FmtWriter<'w, W: std::io::Write> {
+    inner: &'w mut W,
+    error: Option<std::io::Error>,
+}
+
+#[cfg(any(
+    feature = "spv-out",
+    feature = "glsl-out",
+    feature = "wgsl-out",
+    feature = "hlsl-out",
+    feature = "msl-out"
+))]
+impl<'w, W: std::io::Write> FmtWriter<'w, W> {
+    fn new(inner: &'w mut W) -> Self {
+        FmtWriter { inner, error: None }
+    }
+
+    /// Writes SPIR-V words, which don't go through `fmt::Write`.
+    #[cfg(feature = "spv-out")]
+    fn write_words(&mut self, words: Vec<u32>) -> Result<(), std::io::Error> {
+        use byteorder::{WriteBytesExt, LE};
+
+        for w in words {
+            self.inner.write_u32::<LE>(w)?;
         }
         Ok(())
     }
+
+    fn finish(self) -> Result<(), std::io::Error> {
+        match self.error {
+            Some(err) => Err(err),
+            None => self.inner.flush(),
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "spv-out",
+    feature = "glsl-out",
+    feature = "wgsl-out",
+    feature = "hlsl-out",
+    feature = "msl-out"
+))]
+impl<W: std::io::Write> std::fmt::Write for FmtWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|err| {
+            self.error = Some(err);
+            std::fmt::Error
+        })
+    }
 }
 
 impl Display for ShaderLanguage {
@@ -286,41 +515,87 @@ impl Display for ShaderLanguage {
 }
 
 impl FromStr for ShaderLanguage {
-    type Err = ();
+    type Err = ParseLanguageError;
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
-        Ok(match value.to_ascii_lowercase().as_str() {
+        let lang = match value.to_ascii_lowercase().as_str() {
             "wgsl" => ShaderLanguage::WGSL,
             "glsl" => ShaderLanguage::GLSL,
             "spv" => ShaderLanguage::SPV,
             "hlsl" => ShaderLanguage::HLSL,
             "msl" => ShaderLanguage::MSL,
-            _ => return Err(()),
-        })
+            _ => return Err(ParseLanguageError::Unknown(value.to_string())),
+        };
+
+        if !lang.enabled() {
+            return Err(ParseLanguageError::FeatureDisabled(lang));
+        }
+        Ok(lang)
     }
 }
 
+/// Extensions of all source files recognized by enabled input languages.
+pub fn all_source_extensions() -> Vec<&'static str> {
+    ShaderLanguage::ALL
+        .into_iter()
+        .flat_map(ShaderLanguage::extensions)
+        .copied()
+        .collect()
+}
+
 #[derive(Debug)]
 pub struct ShaderFile {
     pub language: ShaderLanguage,
     pub path: PathBuf,
     pub stage: Option<ShaderStage>,
+    /// Identifier used instead of the file name.
+    pub name: Option<String>,
+    /// Workgroup size of the compute entry point this file was generated for.
+    pub workgroup_size: Option<[u32; 3]>,
+    /// Inputs of the vertex entry point this file was generated for.
+    pub vertex_attributes: Vec<VertexAttribute>,
+    /// Source file this one was generated from.
+    pub source: Option<PathBuf>,
+    /// Whether the file is gzip compressed.
+    pub compressed: bool,
+    /// Textual listing written next to the file, SPIR-V disassembly with the
+    /// "spv-asm-out" feature. Generated sources don't refer to it.
+    pub listing: Option<PathBuf>,
+    /// Names of renamed identifiers in the file.
+    pub names: NameMap,
 }
 
 impl ShaderFile {
+    /// File of `language` at `path`, with all optional information left out.
+    pub fn new(language: ShaderLanguage, path: impl Into<PathBuf>) -> ShaderFile {
+        ShaderFile {
+            language,
+            path: path.into(),
+            stage: None,
+            name: None,
+            workgroup_size: None,
+            vertex_attributes: vec![],
+            source: None,
+            compressed: false,
+            listing: None,
+            names: NameMap::default(),
+        }
+    }
+
     pub fn name(&self) -> String {
-        let mut result = file_prefix(&self.path)
-            .and_then(|os_str| os_str.to_str())
-            .expect("invalid shader file name")
-            .to_ascii_uppercase()
-            .replace('.', "_");
+        let mut result = sanitize_identifier(
+            &match &self.name {
+                Some(name) => name.as_str(),
+                None => file_prefix(&self.path)
+                    .and_then(|os_str| os_str.to_str())
+                    .expect("invalid shader file name"),
+            }
+            .to_ascii_uppercase(),
+        );
 
         if let Some(stage) = self.stage {
-            result.push_str(match stage {
-                ShaderStage::Vertex => "_VERT",
-                ShaderStage::Fragment => "_FRAG",
-                ShaderStage::Compute => "_COMP",
-            });
+            result.push('_');
+            result.push_str(&stage.short_name().to_ascii_uppercase());
         }
 
         result
@@ -335,7 +610,7 @@ impl PartialEq for ShaderFile {
 impl Eq for ShaderFile {}
 impl PartialOrd for ShaderFile {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.path.partial_cmp(&other.path)
+        Some(self.cmp(other))
     }
 }
 impl Ord for ShaderFile {
@@ -344,19 +619,127 @@ impl Ord for ShaderFile {
     }
 }
 
+impl Shader {
+    /// Path `lang` output for `stage` is written to.
+    pub fn target_path(
+        &self,
+        config: &Config,
+        lang: ShaderLanguage,
+        stage: Option<ShaderStage>,
+    ) -> PathBuf {
+        config
+            .target_dir(lang)
+            .join(self.target_relative_path(config, lang, stage))
+    }
+
+    /// Path of `lang` output for `stage`, relative to the `lang` output directory.
+    pub fn target_relative_path(
+        &self,
+        config: &Config,
+        lang: ShaderLanguage,
+        stage: Option<ShaderStage>,
+    ) -> PathBuf {
+        self.qualified_path(config, lang, stage, None)
+    }
+
+    fn qualified_path(
+        &self,
+        config: &Config,
+        lang: ShaderLanguage,
+        stage: Option<ShaderStage>,
+        qualifier: Option<&str>,
+    ) -> PathBuf {
+        let qualifiers: Vec<&str> = self
+            .variant
+            .as_deref()
+            .into_iter()
+            .chain(qualifier)
+            .collect();
+        let ext = if qualifiers.is_empty() {
+            lang.get_ext(stage)
+        } else {
+            format!("{}.{}", qualifiers.join("."), lang.get_ext(stage))
+        };
+        config
+            .output_prefix(&self.root)
+            .join(self.path.with_extension(ext))
+    }
+}
+
 pub trait Transpile {
-    fn transpile_and_write<'a>(
+    fn transpile_and_write(&self, config: &Config)
+        -> Result<CodegenData, TranspileError>;
+
+    /// Transpiles into a scratch directory instead of `config.out` and compares
+    /// the results with outputs in it, without writing to it. Returns codegen
+    /// data for outputs in `config.out` along with paths of outputs that are
+    /// missing or differ.
+    fn check_outputs(
         &self,
-        config: &'a Config,
-    ) -> Result<CodegenData, TranspileError<'a>>;
+        config: &Config,
+    ) -> Result<(CodegenData, Vec<PathBuf>), TranspileError>;
+}
+
+/// Config shaders are transpiled with and the directory outputs are written
+/// to, which is `config.out` unless outputs are only being checked.
+pub(crate) struct Destination<'a> {
+    pub config: &'a Config,
+    pub out: &'a Path,
+    /// Shared by all shaders written to the destination.
+    pub validator: RefCell<Validator>,
+}
+
+impl<'a> Destination<'a> {
+    fn new(config: &'a Config) -> Self {
+        Destination {
+            config,
+            out: &config.out,
+            validator: RefCell::new(config.validator()),
+        }
+    }
+
+    pub fn target_dir(&self, target: ShaderLanguage) -> PathBuf {
+        self.config.layout_dir(self.out, target)
+    }
 }
 
 impl Transpile for Shader {
-    fn transpile_and_write<'a>(
+    fn transpile_and_write(
         &self,
-        config: &'a Config,
-    ) -> Result<CodegenData, TranspileError<'a>> {
-        let module = self.module.as_ref().expect("shader module must exist");
+        config: &Config,
+    ) -> Result<CodegenData, TranspileError> {
+        self.transpile_into(&Destination::new(config))
+    }
+
+    fn check_outputs(
+        &self,
+        config: &Config,
+    ) -> Result<(CodegenData, Vec<PathBuf>), TranspileError> {
+        check_with(config, false, |dest| self.transpile_into(dest))
+    }
+}
+
+impl Shader {
+    fn transpile_into(&self, dest: &Destination) -> Result<CodegenData, TranspileError> {
+        let config = dest.config;
+        assert!(self.module.is_some(), "shader module must exist");
+        stage_span!("transpile", self.path);
+
+        if !self.variants.is_empty() {
+            if self.lang == ShaderLanguage::GLSL {
+                let mut result = CodegenData::default();
+                let variants =
+                    self.build_variants(config, &mut dest.validator.borrow_mut())?;
+                for variant in variants {
+                    result += variant.transpile_into(dest)?;
+                }
+                return Ok(result);
+            }
+            log::warn!(
+                "Ignoring variants of {}: defines are only supported in GLSL sources",
+                self.path.display()
+            );
+        }
 
         let mut result = CodegenData::default();
 
@@ -368,49 +751,95 @@ impl Transpile for Shader {
         result.register_source(
             source_lang,
             ShaderFile {
-                language: ShaderLanguage::from_file_name(&self.path).unwrap(),
-                path: self.path.to_path_buf(),
-                stage: None,
+                name: self.name.clone(),
+                ..ShaderFile::new(
+                    source_lang,
+                    config.src_include_path(&self.root).join(&self.path),
+                )
             },
         );
 
-        for &target in &config.targets {
-            let target_dir = &config.out.join(target.to_str());
+        if config.generated_structs {
+            result.register_structs(
+                config.output_prefix(&self.root).join(&self.path),
+                self.uniform_structs(),
+            );
+        }
+
+        #[cfg(feature = "dump-ir")]
+        if config.dump_ir {
+            dump_ir(self, dest)?;
+        }
+
+        #[cfg(feature = "wgsl-out")]
+        if config.dump_intermediate_wgsl && source_lang != ShaderLanguage::WGSL {
+            dump_intermediate_wgsl(self, dest)?;
+        }
+
+        let targets = match &self.targets {
+            Some(targets) => targets.clone(),
+            None => config.targets_for(source_lang),
+        };
+        let no_targets = targets.is_empty();
+        let mut skipped: Vec<String> = vec![];
+        for target in targets {
+            if !target.can_output() {
+                warn_feature_disabled(
+                    format_args!("{} target of {}", target, self.path.display()),
+                    &[target.output_feature()],
+                );
+                skipped.push(format!("{} output is disabled", target));
+                continue;
+            }
+
+            let target_dir = &dest.target_dir(target);
 
             if !target_dir.exists() {
-                std::fs::create_dir(&target_dir)?;
+                std::fs::create_dir_all(target_dir)?;
+            }
+
+            if config.passthrough && target == source_lang {
+                write_passthrough(self, dest, target, &mut result)?;
+                continue;
             }
 
-            if module.entry_points.len() > 1 {
+            let entry_points = self.selected_entry_points();
+            #[cfg(feature = "web-glsl-out")]
+            let entry_points = web_glsl_entry_points(self, target, entry_points);
+            if entry_points.len() > 1 {
                 match target {
                     ShaderLanguage::WGSL | ShaderLanguage::SPV => {
                         log::info!("Generating {} module...", target.to_uppercase_str());
-                        let entry_point = &module.entry_points[0];
-
-                        let transpiled =
-                            transpile_entry(self, Some(entry_point), target)?;
-                        std::fs::write(
-                            &config
-                                .out
-                                .join(target.to_str())
-                                .join(self.path.with_extension(target.get_ext(None))),
-                            transpiled,
-                        )?;
-                        result.register_result(
+                        let entry_point = entry_points[0];
+                        // Modules are named after their stage like single entry
+                        // point outputs as long as all entry points agree on it.
+                        let stage_entry = entry_points
+                            .iter()
+                            .all(|other| {
+                                other.stage == entry_point.stage
+                                    && other.workgroup_size == entry_point.workgroup_size
+                            })
+                            .then_some(entry_point);
+
+                        write_output(
+                            self,
+                            dest,
                             target,
-                            ShaderFile {
-                                language: target,
-                                stage: None,
-                                path: config
-                                    .out_relative()
-                                    .join(target.to_str())
-                                    .join(self.path.with_extension(target.get_ext(None))),
-                            },
-                        );
+                            Some(entry_point),
+                            stage_entry,
+                            None,
+                            &mut result,
+                        )?;
                     }
                     ShaderLanguage::GLSL | ShaderLanguage::HLSL | ShaderLanguage::MSL => {
                         log::info!("Generating {} files...", target.to_uppercase_str());
-                        for entry_point in &module.entry_points {
+                        for entry_point in entry_points.iter().copied() {
+                            // entry points sharing a stage would write the same file
+                            let shares_stage = entry_points
+                                .iter()
+                                .filter(|other| other.stage == entry_point.stage)
+                                .count()
+                                > 1;
                             log::info!(
                                 "- {} {} shader entry point: {}",
                                 target.to_uppercase_str(),
@@ -420,71 +849,466 @@ impl Transpile for Shader {
                                     None => "<no_function>",
                                 }
                             );
-                            let transpiled =
-                                transpile_entry(self, Some(entry_point), target)?;
-
-                            std::fs::write(
-                                &target_dir.join(self.path.with_extension(
-                                    target.get_ext(Some(entry_point.stage)),
-                                )),
-                                transpiled,
-                            )?;
-
-                            result.register_result(
+                            write_output(
+                                self,
+                                dest,
                                 target,
-                                ShaderFile {
-                                    language: target,
-                                    stage: Some(entry_point.stage),
-                                    path: config
-                                        .out_relative()
-                                        .join(target.to_str())
-                                        .join(self.path.with_extension(
-                                            target.get_ext(Some(entry_point.stage)),
-                                        )),
-                                },
-                            );
+                                Some(entry_point),
+                                Some(entry_point),
+                                shares_stage.then_some(entry_point.name.as_str()),
+                                &mut result,
+                            )?;
                         }
                     }
                 }
-            } else if !module.entry_points.is_empty() {
-                let entry_point = &module.entry_points[0];
-                let transpiled = transpile_entry(self, Some(entry_point), target)?;
-                std::fs::write(
-                    &target_dir.join(
-                        self.path
-                            .with_extension(target.get_ext(Some(entry_point.stage))),
-                    ),
-                    transpiled,
-                )?;
-                result.register_result(
+            } else if !entry_points.is_empty() {
+                let entry_point = entry_points[0];
+                write_output(
+                    self,
+                    dest,
                     target,
-                    ShaderFile {
-                        language: target,
-                        stage: Some(entry_point.stage),
-                        path: config.out_relative().join(target.to_str()).join(
-                            self.path
-                                .with_extension(target.get_ext(Some(entry_point.stage))),
-                        ),
-                    },
-                );
+                    Some(entry_point),
+                    Some(entry_point),
+                    None,
+                    &mut result,
+                )?;
+            } else if !target.requires_entry_point() {
+                write_output(self, dest, target, None, None, None, &mut result)?;
             } else {
                 log::info!(
-                    "Skipping shader source with no entry points: {}",
+                    "Skipping {} output for shader source with no entry points: {}",
+                    target.to_uppercase_str(),
                     self.path.display()
                 );
+                skipped.push(format!("{} output requires an entry point", target));
                 continue;
             }
         }
 
+        // sources of skipped identity targets are referred to directly
+        let identity_skipped =
+            config.skip_identity_targets && config.targets.contains(&source_lang);
+        if result.iter_results().next().is_none() && !(no_targets && identity_skipped) {
+            log::warn!(
+                "{} produced no outputs: {}",
+                self.path.display(),
+                if no_targets {
+                    "no targets are configured for it".to_string()
+                } else {
+                    skipped.join(", ")
+                }
+            );
+        }
+
         Ok(result)
     }
 }
 
-fn transpile_entry<'a>(
+/// Leaves compute entry points out of GLSL outputs, WebGL 2 has no compute
+/// shaders.
+#[cfg(feature = "web-glsl-out")]
+fn web_glsl_entry_points<'a>(
+    shader: &Shader,
+    target: ShaderLanguage,
+    entry_points: Vec<&'a EntryPoint>,
+) -> Vec<&'a EntryPoint> {
+    if target != ShaderLanguage::GLSL {
+        return entry_points;
+    }
+    entry_points
+        .into_iter()
+        .filter(|entry_point| {
+            let supported = entry_point.stage != ShaderStage::Compute;
+            if !supported {
+                log::warn!(
+                    "Skipping GLSL output of compute entry point {} of {}: WebGL 2 has no compute shaders",
+                    entry_point.name,
+                    shader.path.display()
+                );
+            }
+            supported
+        })
+        .collect()
+}
+
+#[cfg(feature = "dump-ir")]
+fn dump_ir(shader: &Shader, dest: &Destination) -> Result<(), std::io::Error> {
+    let config = dest.config;
+    let module = shader.module.as_ref().expect("shader module must exist");
+    let out_path = dest
+        .out
+        .join("ir")
+        .join(config.output_prefix(&shader.root))
+        .join(shader.path.with_extension(match &shader.variant {
+            Some(variant) => format!("{}.ron", variant),
+            None => "ron".to_string(),
+        }));
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let ir = ron::ser::to_string_pretty(module, ron::ser::PrettyConfig::default())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    log::debug!("Writing IR: {}", out_path.display());
+    write_atomic(out_path, ir)
+}
+
+/// Writes WGSL generated from the module into the WGSL output directory without
+/// registering it.
+#[cfg(feature = "wgsl-out")]
+fn dump_intermediate_wgsl(
+    shader: &Shader,
+    dest: &Destination,
+) -> Result<(), TranspileError> {
+    let mut path =
+        dest.target_dir(ShaderLanguage::WGSL)
+            .join(shader.target_relative_path(
+                dest.config,
+                ShaderLanguage::WGSL,
+                shader.source_stage,
+            ));
+    path.set_extension("debug.wgsl");
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let wgsl = transpile_entry(shader, None, ShaderLanguage::WGSL)?;
+    log::debug!("Writing intermediate WGSL: {}", path.display());
+    Ok(write_atomic(path, wgsl)?)
+}
+
+/// Writes the preprocessed source as is and registers it.
+fn write_passthrough(
+    shader: &Shader,
+    dest: &Destination,
+    target: ShaderLanguage,
+    result: &mut CodegenData,
+) -> Result<(), std::io::Error> {
+    let config = dest.config;
+    let source = match &shader.source {
+        Some(source) => source,
+        None => return Ok(()),
+    };
+    let file_name = shader.target_relative_path(config, target, shader.source_stage);
+
+    let out_path = dest.target_dir(target).join(&file_name);
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    log::info!("Copying {} source: {}", target, shader.path.display());
+    write_atomic(out_path, source)?;
+
+    result.register_result(
+        target,
+        ShaderFile {
+            stage: shader.source_stage,
+            name: shader.name.clone(),
+            source: Some(config.src_include_path(&shader.root).join(&shader.path)),
+            ..ShaderFile::new(target, config.target_include_path(target).join(&file_name))
+        },
+    );
+    Ok(())
+}
+
+/// Streams output for `entry_point` into a file and registers it. `stage_entry`
+/// is the entry point output file naming and metadata are derived from.
+/// `qualifier` is added to the file name and identifier of outputs that would
+/// otherwise collide with other outputs of the same shader.
+fn write_output(
+    shader: &Shader,
+    dest: &Destination,
+    target: ShaderLanguage,
+    entry_point: Option<&EntryPoint>,
+    stage_entry: Option<&EntryPoint>,
+    qualifier: Option<&str>,
+    result: &mut CodegenData,
+) -> Result<(), TranspileError> {
+    let config = dest.config;
+    let stage = stage_entry.map(|entry_point| entry_point.stage);
+    let compressed = cfg!(feature = "compress") && config.compress && target.is_binary();
+    let qualifiers: Vec<&str> = shader
+        .variant
+        .as_deref()
+        .into_iter()
+        .chain(qualifier)
+        .collect();
+    let file_name = shader.qualified_path(config, target, stage, qualifier);
+    let name = if qualifiers.is_empty() {
+        shader.name.clone()
+    } else {
+        Some(format!(
+            "{}_{}",
+            shader.name.clone().unwrap_or_else(|| {
+                file_prefix(&shader.path)
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string()
+            }),
+            qualifiers.join("_")
+        ))
+    };
+
+    let out_path = dest.target_dir(target).join(&file_name);
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // streamed into a temporary file first so a crash can't leave a partial output
+    let temp = temp_path(&out_path);
+    let (written, names) = {
+        let mut writer = CountingWriter::new(BufWriter::new(File::create(&temp)?));
+        let streamed = transpile_to_writer(shader, entry_point, target, &mut writer)
+            .and_then(|names| {
+                writer.flush().map(|_| names).map_err(TranspileError::from)
+            });
+        match streamed {
+            Ok(names) => (writer.count, names),
+            Err(err) => {
+                drop(writer);
+                let _ = std::fs::remove_file(&temp);
+                return Err(err);
+            }
+        }
+    };
+
+    if written == 0 {
+        log::warn!(
+            "Skipping empty {} output for: {}",
+            target.to_uppercase_str(),
+            shader.path.display()
+        );
+        std::fs::remove_file(temp)?;
+        return Ok(());
+    }
+    std::fs::rename(temp, &out_path)?;
+
+    #[cfg(feature = "spv-in")]
+    if target == ShaderLanguage::SPV && config.verify_spv {
+        if let Err(err) = verify_spv(&std::fs::read(&out_path)?) {
+            log::warn!(
+                "Generated SPIR-V for {} can't be parsed back: {:?}",
+                shader.path.display(),
+                err
+            );
+        }
+    }
+
+    #[cfg(feature = "verify-external")]
+    if let Some(entry_point) = stage_entry {
+        verify_external(config, &out_path, target, entry_point)?;
+    }
+
+    #[cfg(feature = "spv-asm-out")]
+    let listing = if target == ShaderLanguage::SPV {
+        write_atomic(
+            out_path.with_extension("spvasm"),
+            disassemble_spv(&std::fs::read(&out_path)?)?,
+        )?;
+        Some(
+            config
+                .target_include_path(target)
+                .join(file_name.with_extension("spvasm")),
+        )
+    } else {
+        None
+    };
+    #[cfg(not(feature = "spv-asm-out"))]
+    let listing = None;
+
+    let file_name = if compressed {
+        #[cfg(feature = "compress")]
+        compress_output(&out_path)?;
+        gz_path(&file_name)
+    } else {
+        file_name
+    };
+
+    result.register_result(
+        target,
+        ShaderFile {
+            stage,
+            name,
+            workgroup_size: stage_entry
+                .filter(|entry_point| entry_point.stage == ShaderStage::Compute)
+                .map(|entry_point| entry_point.workgroup_size),
+            vertex_attributes: stage_entry
+                .zip(shader.module.as_ref())
+                .map(|(entry_point, module)| vertex_attributes(module, entry_point))
+                .unwrap_or_default(),
+            source: Some(config.src_include_path(&shader.root).join(&shader.path)),
+            compressed,
+            listing,
+            names,
+            ..ShaderFile::new(target, config.target_include_path(target).join(&file_name))
+        },
+    );
+
+    Ok(())
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut result = path.as_os_str().to_owned();
+    result.push(".gz");
+    PathBuf::from(result)
+}
+
+/// Replaces the file at `path` with a gzip compressed one next to it.
+#[cfg(feature = "compress")]
+fn compress_output(path: &Path) -> std::io::Result<()> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&std::fs::read(path)?)?;
+    write_atomic(gz_path(path), encoder.finish()?)?;
+    std::fs::remove_file(path)
+}
+
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: usize,
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        CountingWriter { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Renames `OpEntryPoint` instructions in SPIR-V `words` to `name`.
+#[cfg(feature = "spv-out")]
+fn rename_spv_entry_points(words: &mut Vec<u32>, name: &str) {
+    const OP_ENTRY_POINT: u32 = 15;
+    const HEADER_LEN: usize = 5;
+
+    let mut literal: Vec<u32> = name
+        .as_bytes()
+        .chunks(4)
+        .map(|chunk| {
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from_le_bytes(word)
+        })
+        .collect();
+    if name.len().is_multiple_of(4) {
+        literal.push(0);
+    }
+
+    let mut i = HEADER_LEN;
+    while i < words.len() {
+        let count = (words[i] >> 16) as usize;
+        if count == 0 {
+            break;
+        }
+        if words[i] & 0xFFFF == OP_ENTRY_POINT {
+            let name_start = i + 3;
+            let name_end = words[name_start..i + count]
+                .iter()
+                .position(|word| word.to_le_bytes().contains(&0))
+                .map_or(i + count, |pos| name_start + pos + 1);
+            words.splice(name_start..name_end, literal.iter().copied());
+
+            let count = count - (name_end - name_start) + literal.len();
+            words[i] = (count as u32) << 16 | OP_ENTRY_POINT;
+        }
+        i += (words[i] >> 16) as usize;
+    }
+}
+
+/// Checks that `words` is SPIR-V naga is able to parse back, which catches
+/// back-end bugs before drivers get to reject the output.
+#[cfg(feature = "spv-in")]
+pub fn verify_spv(words: &[u8]) -> Result<(), naga::front::spv::Error> {
+    naga::front::spv::parse_u8_slice(words, &naga::front::spv::Options::default())
+        .map(|_| ())
+}
+
+/// Textual listing of the SPIR-V module in `binary`.
+#[cfg(feature = "spv-asm-out")]
+pub fn disassemble_spv(binary: &[u8]) -> Result<String, TranspileError> {
+    use rspirv::binary::Disassemble;
+
+    let module = rspirv::dr::load_bytes(binary).map_err(|err| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", err))
+    })?;
+    Ok(module.disassemble())
+}
+
+/// Checks a GLSL or HLSL output generated for `entry_point` with glslangValidator.
+/// Other targets are ignored, as is a missing validator if none is configured.
+#[cfg(feature = "verify-external")]
+pub fn verify_external(
+    config: &Config,
+    path: &Path,
+    target: ShaderLanguage,
+    entry_point: &EntryPoint,
+) -> Result<(), TranspileError> {
+    let stage = entry_point.stage.short_name();
+    let binary = config
+        .glslang_path
+        .as_deref()
+        .unwrap_or_else(|| Path::new("glslangValidator"));
+
+    let mut command = std::process::Command::new(binary);
+    match target {
+        ShaderLanguage::GLSL => command.args(["-S", stage]),
+        ShaderLanguage::HLSL => {
+            command.args(["-D", "-e", &entry_point.name, "-S", stage])
+        }
+        _ => return Ok(()),
+    };
+    let output = match command.arg(path).output() {
+        Ok(output) => output,
+        Err(err)
+            if err.kind() == std::io::ErrorKind::NotFound
+                && config.glslang_path.is_none() =>
+        {
+            log::debug!("glslangValidator not found, skipping: {}", path.display());
+            return Ok(());
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    if output.status.success() {
+        return Ok(());
+    }
+    // glslangValidator reports errors on stdout
+    let mut message = String::from_utf8_lossy(&output.stdout).into_owned();
+    message.push_str(&String::from_utf8_lossy(&output.stderr));
+    Err(TranspileError::ExternalValidation {
+        path: path.to_path_buf(),
+        output: message.trim().to_string(),
+    })
+}
+
+/// Transpiles `shader` for `entry_point` directly into `writer`, returning names
+/// the backend reported for renamed identifiers. SPIR-V modules are still built
+/// in memory first, see `ShaderLanguage::generate_to`.
+pub fn transpile_to_writer(
     shader: &Shader,
     entry_point: Option<&EntryPoint>,
     target: ShaderLanguage,
-) -> Result<ShaderCode, TranspileError<'a>> {
+    writer: &mut impl Write,
+) -> Result<NameMap, TranspileError> {
+    target.generate_to(shader, writer, entry_point)
+}
+
+pub(crate) fn transpile_entry(
+    shader: &Shader,
+    entry_point: Option<&EntryPoint>,
+    target: ShaderLanguage,
+) -> Result<ShaderCode, TranspileError> {
     let mut transpiled = if target.is_binary() {
         ShaderCode::Binary(Vec::with_capacity(512))
     } else {
@@ -496,12 +1320,12 @@ fn transpile_entry<'a>(
     Ok(transpiled)
 }
 
-impl Transpile for Vec<Shader> {
-    fn transpile_and_write<'a>(
+impl Transpile for [Shader] {
+    fn transpile_and_write(
         &self,
-        config: &'a Config,
-    ) -> Result<CodegenData, TranspileError<'a>> {
-        let mut result = CodegenData::default();
+        config: &Config,
+    ) -> Result<CodegenData, TranspileError> {
+        config.validate().map_err(SourceError::from)?;
 
         // Remove previously generated files
         if config.out.exists() {
@@ -509,20 +1333,278 @@ impl Transpile for Vec<Shader> {
             std::fs::remove_dir_all(&config.out)?;
             std::fs::create_dir_all(&config.out)?;
         }
-        for shader in self {
-            match shader.transpile_and_write(config) {
-                Ok(data) => result += data,
-                Err(err) => {
-                    log::error!(
-                        "Encountered errors while transpiling: {}\n{:#?}",
-                        shader.path.display(),
-                        err
-                    );
-                    return Err(err);
-                }
-            };
+        transpile_all(self, &Destination::new(config))
+    }
+
+    fn check_outputs(
+        &self,
+        config: &Config,
+    ) -> Result<(CodegenData, Vec<PathBuf>), TranspileError> {
+        config.validate().map_err(SourceError::from)?;
+        check_with(config, true, |dest| transpile_all(self, dest))
+    }
+}
+
+impl Transpile for Shaders {
+    fn transpile_and_write(
+        &self,
+        config: &Config,
+    ) -> Result<CodegenData, TranspileError> {
+        self.as_slice().transpile_and_write(config)
+    }
+
+    fn check_outputs(
+        &self,
+        config: &Config,
+    ) -> Result<(CodegenData, Vec<PathBuf>), TranspileError> {
+        self.as_slice().check_outputs(config)
+    }
+}
+
+fn transpile_all(
+    shaders: &[Shader],
+    dest: &Destination,
+) -> Result<CodegenData, TranspileError> {
+    let mut result = CodegenData::default();
+    for shader in shaders {
+        match shader.transpile_into(dest) {
+            Ok(data) => result += data,
+            Err(err) => {
+                log::error!(
+                    "Encountered errors while transpiling: {}\n{:#?}",
+                    shader.path.display(),
+                    err
+                );
+                return Err(err);
+            }
+        };
+    }
+
+    #[cfg(all(feature = "wgsl-in", feature = "wgsl-out"))]
+    if dest.config.bundle_wgsl {
+        crate::language::bundle::write_bundle(shaders, dest, &mut result)?;
+    }
+
+    Ok(result)
+}
+
+/// Runs `transpile` against a scratch directory and compares files it wrote
+/// with ones in `config.out`. With `leftovers`, files in `config.out` that
+/// weren't written are reported as well.
+fn check_with(
+    config: &Config,
+    leftovers: bool,
+    transpile: impl FnOnce(&Destination) -> Result<CodegenData, TranspileError>,
+) -> Result<(CodegenData, Vec<PathBuf>), TranspileError> {
+    let scratch = TempDir::new("starch-check")?;
+    let data = transpile(&Destination {
+        config,
+        out: &scratch,
+        validator: RefCell::new(config.validator()),
+    })?;
+
+    let mut stale = vec![];
+    for path in collect_files(&scratch, |_| true) {
+        let existing = config.out.join(&path);
+        if std::fs::read(&existing).ok() != Some(std::fs::read(scratch.join(&path))?) {
+            stale.push(existing);
         }
+    }
+    if leftovers && config.out.exists() {
+        stale.extend(
+            collect_files(&config.out, |path| {
+                path.file_name() != Some(OsStr::new(MANIFEST_FILE_NAME))
+            })
+            .into_iter()
+            .filter(|path| !scratch.join(path).exists())
+            .map(|path| config.out.join(path)),
+        );
+    }
+    Ok((data, stale))
+}
 
-        Ok(result)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_types_match_encoding() {
+        assert_eq!(ShaderLanguage::SPV.mime_type(), "application/octet-stream");
+        assert_eq!(
+            ShaderLanguage::SPV.content_type(),
+            "application/octet-stream"
+        );
+        assert_eq!(ShaderLanguage::WGSL.mime_type(), "text/plain");
+        assert_eq!(
+            ShaderLanguage::GLSL.content_type(),
+            "text/plain; charset=utf-8"
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "wgsl-in", feature = "glsl-in"))]
+    fn languages_are_found_by_extension() {
+        assert_eq!(
+            ShaderLanguage::from_extension("wgsl"),
+            Some(ShaderLanguage::WGSL)
+        );
+        assert_eq!(
+            ShaderLanguage::from_extension(".FRAG"),
+            Some(ShaderLanguage::GLSL)
+        );
+        assert_eq!(ShaderLanguage::from_extension("hlsl"), None);
+        assert_eq!(ShaderLanguage::from_extension(""), None);
+        assert_eq!(
+            ShaderLanguage::from_file_name("post/blur.comp"),
+            ShaderLanguage::from_extension("comp")
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "wgsl-in", feature = "glsl-out"))]
+    fn glsl_options_are_applied() {
+        use crate::config::{GlslBinding, GlslOptions};
+        use crate::shader::tests::parsed_fixture;
+
+        let load = |path: &str, glsl: GlslOptions| {
+            let mut shader = parsed_fixture(path);
+            shader.glsl = glsl;
+            shader
+        };
+        let glsl = |shader: &Shader| {
+            let entry_point = &shader.entry_points()[0];
+            transpile_entry(shader, Some(entry_point), ShaderLanguage::GLSL)
+                .unwrap()
+                .unwrap_text()
+                .to_string()
+        };
+
+        let triangle = load("./test/src/triangle.wgsl", GlslOptions::default());
+        assert!(glsl(&triangle).contains("gl_Position.yz"));
+        let triangle = load(
+            "./test/src/triangle.wgsl",
+            GlslOptions {
+                writer_flags: Some(vec![]),
+                ..Default::default()
+            },
+        );
+        assert!(!glsl(&triangle).contains("gl_Position.yz"));
+
+        let binding: GlslBinding = "0.0=3".parse().unwrap();
+        assert_eq!(
+            binding,
+            GlslBinding {
+                group: 0,
+                binding: 0,
+                slot: 3
+            }
+        );
+        // WebGL 2 doesn't support explicit bindings
+        #[cfg(not(feature = "web-glsl-out"))]
+        {
+            let invert = load(
+                "./test/src/post/invert.wgsl",
+                GlslOptions {
+                    binding_map: vec![binding],
+                    ..Default::default()
+                },
+            );
+            assert!(glsl(&invert).contains("binding = 3"));
+        }
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "verify-external", feature = "wgsl-in"))]
+    fn external_validator_output_is_reported() {
+        use crate::shader::tests::parsed_fixture;
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = crate::util::TempDir::new("starch-verify-external").unwrap();
+        let validator = root.join("glslangValidator");
+        std::fs::write(
+            &validator,
+            "#!/bin/sh\necho \"ERROR: rejected $2\"\nexit 2\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&validator, std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+        let config = Config {
+            glslang_path: Some(validator),
+            ..Config::init(&root)
+        };
+
+        let shader = parsed_fixture("./test/src/double.wgsl");
+        let entry_point = &shader.entry_points()[0];
+
+        let output = root.join("double.comp.glsl");
+        match verify_external(&config, &output, ShaderLanguage::GLSL, entry_point) {
+            Err(TranspileError::ExternalValidation { path, output }) => {
+                assert_eq!(path, root.join("double.comp.glsl"));
+                assert_eq!(output, "ERROR: rejected comp");
+            }
+            other => panic!("expected external validation error, got {:?}", other),
+        }
+        assert!(
+            verify_external(&config, &output, ShaderLanguage::SPV, entry_point).is_ok()
+        );
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "wgsl-in",
+        feature = "glsl-out",
+        feature = "hlsl-out",
+        not(feature = "web-glsl-out")
+    ))]
+    fn backend_names_are_reported() {
+        use crate::shader::tests::parsed_fixture;
+
+        let root = crate::util::TempDir::new("starch-backend-names").unwrap();
+        let path = root.join("scale.wgsl");
+        std::fs::write(
+            &path,
+            "struct Params { factor: f32 }\n\
+             @group(0) @binding(0) var<uniform> params: Params;\n\
+             @group(0) @binding(1) var<storage, read_write> data: array<f32>;\n\
+             @compute @workgroup_size(64)\n\
+             fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {\n\
+             data[id.x] = data[id.x] * params.factor;\n\
+             }\n",
+        )
+        .unwrap();
+
+        let shader = parsed_fixture(&path);
+        let entry_point = &shader.entry_points()[0];
+
+        let mut glsl = vec![];
+        let names = transpile_to_writer(
+            &shader,
+            Some(entry_point),
+            ShaderLanguage::GLSL,
+            &mut glsl,
+        )
+        .unwrap();
+        let block = &names.globals["params"];
+        assert!(String::from_utf8(glsl).unwrap().contains(block.as_str()));
+
+        let mut hlsl = vec![];
+        let names =
+            transpile_to_writer(&shader, None, ShaderLanguage::HLSL, &mut hlsl).unwrap();
+        let main = &names.entry_points["cs_main"];
+        assert!(String::from_utf8(hlsl).unwrap().contains(main.as_str()));
+    }
+
+    #[test]
+    #[cfg(all(feature = "wgsl-in", feature = "spv-in", feature = "spv-out"))]
+    fn generated_spv_is_verified() {
+        use crate::shader::tests::parsed_fixture;
+
+        let shader = parsed_fixture("./test/src/double.wgsl");
+
+        let entry_point = &shader.entry_points()[0];
+        let spv =
+            transpile_entry(&shader, Some(entry_point), ShaderLanguage::SPV).unwrap();
+        assert!(verify_spv(spv.unwrap_binary()).is_ok());
+        assert!(verify_spv(&[0, 1, 2, 3]).is_err());
     }
 }
@@ -0,0 +1,449 @@
+use crate::util::sanitize_identifier;
+use naga::proc::Layouter;
+use naga::{
+    AddressSpace, ArraySize, Binding, EntryPoint, Handle, Module, ResourceBinding,
+    ScalarKind, ShaderStage, Type, TypeInner, VectorSize,
+};
+#[cfg(feature = "config-file")]
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Memory layout of a struct used by a buffer, as computed by naga.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config-file", derive(Serialize))]
+pub struct StructLayout {
+    pub ty: Handle<Type>,
+    /// Name of the struct, `Struct<index>` if it's anonymous.
+    pub name: String,
+    /// Size of the struct including tail padding.
+    pub size: u32,
+    pub members: Vec<MemberLayout>,
+    /// Buffers of this struct type, empty for structs only nested in others.
+    pub buffers: Vec<BufferBinding>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config-file", derive(Serialize))]
+pub struct MemberLayout {
+    pub name: String,
+    pub ty: Handle<Type>,
+    pub offset: u32,
+    /// Size of the member, zero for runtime sized arrays.
+    pub size: u32,
+    /// Rust type with a matching layout, if there is one.
+    pub rust_type: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "config-file", derive(Serialize))]
+pub struct BufferBinding {
+    /// Name of the global variable.
+    pub name: Option<String>,
+    pub space: AddressSpace,
+    pub binding: Option<ResourceBinding>,
+}
+
+/// Collects layouts of structs used by uniform, storage and push constant
+/// buffers of `module`, including structs nested in them.
+pub fn buffer_structs(module: &Module) -> Vec<StructLayout> {
+    let mut layouter = Layouter::default();
+    if let Err(err) = layouter.update(&module.types, &module.constants) {
+        log::error!("unable to compute type layouts: {}", err);
+        return vec![];
+    }
+
+    let mut result: BTreeMap<usize, StructLayout> = BTreeMap::new();
+    for (_, global) in module.global_variables.iter() {
+        if !matches!(
+            global.space,
+            AddressSpace::Uniform
+                | AddressSpace::Storage { .. }
+                | AddressSpace::PushConstant
+        ) {
+            continue;
+        }
+
+        collect_structs(module, &layouter, global.ty, &mut result);
+        if let Some(layout) = result.get_mut(&global.ty.index()) {
+            layout.buffers.push(BufferBinding {
+                name: global.name.clone(),
+                space: global.space,
+                binding: global.binding.clone(),
+            });
+        }
+    }
+
+    result.into_values().collect()
+}
+
+/// Vertex input of an entry point bound to a `@location`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "config-file", derive(Serialize))]
+pub struct VertexAttribute {
+    pub location: u32,
+    /// Name of the matching `wgpu::VertexFormat` variant.
+    pub format: &'static str,
+    /// Size of the attribute in bytes.
+    pub size: u32,
+}
+
+/// Collects `@location` inputs of a vertex `entry_point`, including members of
+/// struct arguments, ordered by location. Inputs without a matching vertex
+/// format are left out.
+pub fn vertex_attributes(
+    module: &Module,
+    entry_point: &EntryPoint,
+) -> Vec<VertexAttribute> {
+    if entry_point.stage != ShaderStage::Vertex {
+        return vec![];
+    }
+    let mut layouter = Layouter::default();
+    if let Err(err) = layouter.update(&module.types, &module.constants) {
+        log::error!("unable to compute type layouts: {}", err);
+        return vec![];
+    }
+
+    let mut inputs = vec![];
+    for argument in &entry_point.function.arguments {
+        match (&argument.binding, &module.types[argument.ty].inner) {
+            (Some(binding), _) => inputs.push((binding, argument.ty)),
+            (None, TypeInner::Struct { members, .. }) => inputs.extend(
+                members
+                    .iter()
+                    .filter_map(|member| Some((member.binding.as_ref()?, member.ty))),
+            ),
+            _ => {}
+        }
+    }
+
+    let mut result: Vec<VertexAttribute> = inputs
+        .into_iter()
+        .filter_map(|(binding, ty)| {
+            let location = match binding {
+                Binding::Location { location, .. } => *location,
+                Binding::BuiltIn(_) => return None,
+            };
+            let format = vertex_format(&module.types[ty].inner);
+            if format.is_none() {
+                log::warn!(
+                    "Vertex input at location {} of {} has no matching vertex format",
+                    location,
+                    entry_point.name
+                );
+            }
+            Some(VertexAttribute {
+                location,
+                format: format?,
+                size: layouter[ty].size,
+            })
+        })
+        .collect();
+    result.sort_by_key(|attribute| attribute.location);
+    result
+}
+
+fn vertex_format(inner: &TypeInner) -> Option<&'static str> {
+    let (kind, width, size) = match *inner {
+        TypeInner::Scalar { kind, width } => (kind, width, None),
+        TypeInner::Vector { size, kind, width } => (kind, width, Some(size)),
+        _ => return None,
+    };
+    Some(match (kind, width, size) {
+        (ScalarKind::Float, 4, None) => "Float32",
+        (ScalarKind::Float, 4, Some(VectorSize::Bi)) => "Float32x2",
+        (ScalarKind::Float, 4, Some(VectorSize::Tri)) => "Float32x3",
+        (ScalarKind::Float, 4, Some(VectorSize::Quad)) => "Float32x4",
+        (ScalarKind::Float, 8, None) => "Float64",
+        (ScalarKind::Float, 8, Some(VectorSize::Bi)) => "Float64x2",
+        (ScalarKind::Float, 8, Some(VectorSize::Tri)) => "Float64x3",
+        (ScalarKind::Float, 8, Some(VectorSize::Quad)) => "Float64x4",
+        (ScalarKind::Uint, 4, None) => "Uint32",
+        (ScalarKind::Uint, 4, Some(VectorSize::Bi)) => "Uint32x2",
+        (ScalarKind::Uint, 4, Some(VectorSize::Tri)) => "Uint32x3",
+        (ScalarKind::Uint, 4, Some(VectorSize::Quad)) => "Uint32x4",
+        (ScalarKind::Sint, 4, None) => "Sint32",
+        (ScalarKind::Sint, 4, Some(VectorSize::Bi)) => "Sint32x2",
+        (ScalarKind::Sint, 4, Some(VectorSize::Tri)) => "Sint32x3",
+        (ScalarKind::Sint, 4, Some(VectorSize::Quad)) => "Sint32x4",
+        _ => return None,
+    })
+}
+
+fn collect_structs(
+    module: &Module,
+    layouter: &Layouter,
+    ty: Handle<Type>,
+    result: &mut BTreeMap<usize, StructLayout>,
+) {
+    match &module.types[ty].inner {
+        TypeInner::Array { base, .. } => collect_structs(module, layouter, *base, result),
+        TypeInner::Struct { members, span } => {
+            if result.contains_key(&ty.index()) {
+                return;
+            }
+            let members = members
+                .iter()
+                .enumerate()
+                .map(|(i, member)| {
+                    collect_structs(module, layouter, member.ty, result);
+                    let runtime_sized = matches!(
+                        module.types[member.ty].inner,
+                        TypeInner::Array {
+                            size: ArraySize::Dynamic,
+                            ..
+                        }
+                    );
+                    MemberLayout {
+                        name: member
+                            .name
+                            .clone()
+                            .unwrap_or_else(|| format!("member{}", i)),
+                        ty: member.ty,
+                        offset: member.offset,
+                        size: if runtime_sized {
+                            0
+                        } else {
+                            layouter[member.ty].size
+                        },
+                        rust_type: rust_type(module, layouter, member.ty),
+                    }
+                })
+                .collect();
+
+            result.insert(
+                ty.index(),
+                StructLayout {
+                    ty,
+                    name: struct_name(module, ty),
+                    size: *span,
+                    members,
+                    buffers: vec![],
+                },
+            );
+        }
+        _ => {}
+    }
+}
+
+fn struct_name(module: &Module, ty: Handle<Type>) -> String {
+    match &module.types[ty].name {
+        Some(name) => sanitize_identifier(name),
+        None => format!("Struct{}", ty.index()),
+    }
+}
+
+fn scalar_type(kind: ScalarKind, width: u8) -> Option<&'static str> {
+    Some(match (kind, width) {
+        (ScalarKind::Float, 4) => "f32",
+        (ScalarKind::Float, 8) => "f64",
+        (ScalarKind::Sint, 4) => "i32",
+        (ScalarKind::Sint, 8) => "i64",
+        (ScalarKind::Uint, 4) | (ScalarKind::Bool, _) => "u32",
+        (ScalarKind::Uint, 8) => "u64",
+        _ => return None,
+    })
+}
+
+/// Rust type with the same size and layout as `ty`, if there is one.
+fn rust_type(module: &Module, layouter: &Layouter, ty: Handle<Type>) -> Option<String> {
+    Some(match &module.types[ty].inner {
+        TypeInner::Scalar { kind, width } | TypeInner::Atomic { kind, width } => {
+            scalar_type(*kind, *width)?.to_string()
+        }
+        TypeInner::Vector { size, kind, width } => {
+            format!("[{}; {}]", scalar_type(*kind, *width)?, *size as u8)
+        }
+        TypeInner::Matrix {
+            columns,
+            rows,
+            width,
+        } => {
+            // columns are aligned like vectors, vec3 ones take up as much as vec4
+            let rows = match rows {
+                VectorSize::Bi => 2,
+                VectorSize::Tri | VectorSize::Quad => 4,
+            };
+            format!(
+                "[[{}; {}]; {}]",
+                scalar_type(ScalarKind::Float, *width)?,
+                rows,
+                *columns as u8
+            )
+        }
+        TypeInner::Array {
+            base,
+            size: ArraySize::Constant(size),
+            stride,
+        } => {
+            if layouter[*base].size != *stride {
+                return None;
+            }
+            let length = match module.constants[*size].inner {
+                naga::ConstantInner::Scalar {
+                    value: naga::ScalarValue::Uint(value),
+                    ..
+                } => u32::try_from(value).ok()?,
+                naga::ConstantInner::Scalar {
+                    value: naga::ScalarValue::Sint(value),
+                    ..
+                } => u32::try_from(value).ok()?,
+                _ => return None,
+            };
+            format!("[{}; {}]", rust_type(module, layouter, *base)?, length)
+        }
+        TypeInner::Struct { .. } => struct_name(module, ty),
+        _ => return None,
+    })
+}
+
+/// Writes a `#[repr(C)]` Rust struct matching `layout`. Gaps between members
+/// are filled with explicit padding, members without a matching Rust type are
+/// represented as bytes and runtime sized arrays are left out.
+pub(crate) fn format_struct(
+    layout: &StructLayout,
+    indent: usize,
+    visibility: &str,
+    result: &mut String,
+) {
+    let indent_str = "    ".repeat(indent);
+    let _ = writeln!(result, "{}#[repr(C)]", indent_str);
+    let _ = writeln!(result, "{}#[derive(Debug, Clone, Copy)]", indent_str);
+    let _ = writeln!(
+        result,
+        "{}{} struct {} {{",
+        indent_str, visibility, layout.name
+    );
+
+    let mut position = 0;
+    let mut padding = 0;
+    let mut pad = |result: &mut String, position: &mut u32, until: u32| {
+        if until > *position {
+            let _ = writeln!(
+                result,
+                "{}    {} _pad{}: [u8; {}],",
+                indent_str,
+                visibility,
+                padding,
+                until - *position
+            );
+            padding += 1;
+            *position = until;
+        }
+    };
+
+    let mut size = layout.size;
+    for member in &layout.members {
+        if member.size == 0 {
+            let _ = writeln!(
+                result,
+                "{}    // runtime sized `{}` starts at offset {}",
+                indent_str, member.name, member.offset
+            );
+            size = member.offset;
+            break;
+        }
+
+        pad(result, &mut position, member.offset);
+        let ty = member
+            .rust_type
+            .clone()
+            .unwrap_or_else(|| format!("[u8; {}]", member.size));
+        let _ = writeln!(
+            result,
+            "{}    {} {}: {},",
+            indent_str,
+            visibility,
+            field_name(&member.name),
+            ty
+        );
+        position += member.size;
+    }
+    pad(result, &mut position, size);
+
+    let _ = writeln!(result, "{}}}", indent_str);
+}
+
+fn field_name(name: &str) -> String {
+    let name = sanitize_identifier(name);
+    if crate::language::codegen::RUST_KEYWORDS.contains(&name.as_str()) {
+        format!("r#{}", name)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "wgsl-in")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_struct_layout_is_padded() {
+        let module = naga::front::wgsl::parse_str(
+            "struct Light { position: vec3<f32>, intensity: f32, color: vec3<f32> }\n\
+             struct Globals { lights: array<Light, 2>, view: mat3x3<f32>, count: u32 }\n\
+             @group(0) @binding(1) var<uniform> globals: Globals;\n",
+        )
+        .unwrap();
+
+        let layouts = buffer_structs(&module);
+        assert_eq!(layouts.len(), 2);
+        assert_eq!(layouts[0].name, "Light");
+        assert_eq!(layouts[1].name, "Globals");
+        assert_eq!(layouts[1].buffers[0].name.as_deref(), Some("globals"));
+        assert_eq!(
+            layouts[1].buffers[0].binding,
+            Some(ResourceBinding {
+                group: 0,
+                binding: 1
+            })
+        );
+
+        let mut light = String::new();
+        format_struct(&layouts[0], 0, "pub", &mut light);
+        assert_eq!(
+            light,
+            "#[repr(C)]\n\
+             #[derive(Debug, Clone, Copy)]\n\
+             pub struct Light {\n    \
+                 pub position: [f32; 3],\n    \
+                 pub intensity: f32,\n    \
+                 pub color: [f32; 3],\n    \
+                 pub _pad0: [u8; 4],\n\
+             }\n"
+        );
+
+        let mut globals = String::new();
+        format_struct(&layouts[1], 0, "pub", &mut globals);
+        assert!(globals.contains("pub lights: [Light; 2],"));
+        assert!(globals.contains("pub view: [[f32; 4]; 3],"));
+        assert!(globals.contains("pub count: u32,\n    pub _pad0: [u8; 12],"));
+    }
+
+    #[test]
+    fn vertex_attributes_follow_locations() {
+        let module = naga::front::wgsl::parse_str(
+            "struct VertexInput {\n\
+                 @location(2) color: vec4<f32>,\n\
+                 @builtin(vertex_index) index: u32,\n\
+                 @location(0) position: vec3<f32>,\n\
+             }\n\
+             @vertex\n\
+             fn vs_main(in: VertexInput, @location(1) id: u32) -> @builtin(position) vec4<f32> {\n\
+                 return vec4<f32>(in.position, f32(id));\n\
+             }\n",
+        )
+        .unwrap();
+
+        let attributes = vertex_attributes(&module, &module.entry_points[0]);
+        let formats: Vec<(u32, &str, u32)> = attributes
+            .iter()
+            .map(|it| (it.location, it.format, it.size))
+            .collect();
+        assert_eq!(
+            formats,
+            [(0, "Float32x3", 12), (1, "Uint32", 4), (2, "Float32x4", 16)]
+        );
+    }
+}
@@ -1,24 +1,42 @@
 pub mod config;
 pub mod error;
 pub mod language;
+pub mod layout;
+pub mod meta;
 pub mod preprocess;
+pub mod reflect;
 pub mod shader;
+pub mod source;
 pub(crate) mod util;
 
 pub mod prelude {}
 
 pub mod prelude_build {
     pub use super::config::Config as StarchConfig;
+    pub use super::config::{
+        BoundsCheck, GlslBinding, GlslOptions, IncludePathMode, OutputLayout,
+        StageCapabilities, ValidationPreset,
+    };
     pub use super::error::*;
+    #[cfg(all(feature = "wgsl-in", feature = "wgsl-out"))]
+    pub use super::language::bundle::bundle;
     pub use super::language::codegen::CodegenData;
     pub use super::language::transpile::*;
+    pub use super::layout::{BufferBinding, MemberLayout, StructLayout, VertexAttribute};
+    pub use super::meta::ShaderMeta;
     pub use super::preprocess::preprocess_shader;
+    pub use super::reflect::{
+        BindingReflection, EntryPointReflection, ResourceKind, ShaderReflection,
+    };
     pub use super::shader::*;
+    pub use super::source::*;
+    pub use super::util::StageExt;
 }
 
 #[cfg(test)]
 mod tests {
     use super::prelude_build::*;
+    use crate::util::TempDir;
     use log::LevelFilter;
 
     #[test]
@@ -31,11 +49,279 @@ mod tests {
 
         let shaders = Shader::load_shaders(&config)
             .expect("couldn't parse and validate source shaders");
+        #[cfg(feature = "wgsl-in")]
+        {
+            assert!(shaders.find("triangle").is_some());
+            assert!(shaders.find("post/invert.wgsl").is_some());
+            assert_eq!(
+                shaders.iter_by_language(ShaderLanguage::WGSL).count(),
+                shaders.len() - shaders.iter_by_language(ShaderLanguage::GLSL).count()
+            );
+        }
         let result: CodegenData = shaders
             .transpile_and_write(&config)
             .expect("couldn't transpile");
+        result
+            .write_manifest(&config)
+            .expect("couldn't write manifest");
         result
             .generate_sources(&config)
             .expect("couldn't generate source files");
+
+        // files that can't be told apart by their parent directories
+        let mut colliding = CodegenData::default();
+        for path in ["gen/glsl/a.glsl", "gen/glsl/b.glsl"] {
+            colliding.register_result(
+                ShaderLanguage::GLSL,
+                ShaderFile {
+                    name: Some("same".to_string()),
+                    ..ShaderFile::new(ShaderLanguage::GLSL, path)
+                },
+            );
+        }
+        match colliding.generate_sources(&config) {
+            Err(CodegenError::IdentifierCollision { name, paths }) => {
+                assert_eq!(name, "SAME");
+                assert_eq!(paths.len(), 2);
+            }
+            other => panic!("expected identifier collision, got {:?}", other),
+        }
+
+        // entry points sharing a stage get their own files
+        #[cfg(all(feature = "wgsl-in", feature = "glsl-out"))]
+        {
+            let glsl = config.target_dir(ShaderLanguage::GLSL);
+            assert!(glsl.join("tint.red.frag.glsl").exists());
+            assert!(glsl.join("tint.blue.frag.glsl").exists());
+            assert!(glsl.join("tint.vert.glsl").exists());
+        }
+
+        // WebGL 2 has no compute shaders
+        #[cfg(all(feature = "wgsl-in", feature = "web-glsl-out"))]
+        assert!(!config
+            .target_dir(ShaderLanguage::GLSL)
+            .join("double.comp.glsl")
+            .exists());
+
+        // every variant of a GLSL shader gets its own outputs
+        #[cfg(all(feature = "glsl-in", feature = "glsl-out", feature = "config-file"))]
+        {
+            let glsl = config.target_dir(ShaderLanguage::GLSL);
+            assert!(glsl.join("tiers.quality_1.frag.glsl").exists());
+            assert!(glsl.join("tiers.quality_2.frag.glsl").exists());
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "msl-out"))]
+    fn unsupported_targets_fail_early() {
+        let root = TempDir::new("starch-unsupported-targets").unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        let config = StarchConfig {
+            targets: vec![ShaderLanguage::MSL],
+            ..StarchConfig::init(&root)
+        };
+
+        assert_eq!(
+            config.validate(),
+            Err(ParseLanguageError::FeatureDisabled(ShaderLanguage::MSL))
+        );
+        assert!(matches!(
+            Shader::load_shaders(&config),
+            Err(SourceError::Target(ParseLanguageError::FeatureDisabled(
+                ShaderLanguage::MSL
+            )))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "wgsl-in")]
+    fn shaders_can_be_selected_by_glob() {
+        use std::path::PathBuf;
+
+        let root = TempDir::new("starch-load-matching").unwrap();
+        std::fs::create_dir_all(root.join("src/common")).unwrap();
+        std::fs::create_dir_all(root.join("src/post/blur")).unwrap();
+        std::fs::write(
+            root.join("src/common/math.wgsl"),
+            "fn square(x: f32) -> f32 {\n    return x * x;\n}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("src/post/blur/wide.wgsl"),
+            "@starch::include '/common/math.wgsl'\n\
+             @compute @workgroup_size(1)\n\
+             fn main() {\n    let x = square(2.0);\n}\n",
+        )
+        .unwrap();
+        let config = StarchConfig {
+            targets: vec![],
+            ..StarchConfig::init(&root)
+        };
+
+        let shaders = Shader::load_matching(&config, "post/**/*.wgsl").unwrap();
+        assert_eq!(shaders.len(), 1);
+        assert_eq!(shaders[0].path, PathBuf::from("post/blur/wide.wgsl"));
+        assert!(shaders[0]
+            .dependencies
+            .contains(&PathBuf::from("common/math.wgsl")));
+
+        assert!(Shader::load_matching(&config, "*.wgsl").unwrap().is_empty());
+        assert!(matches!(
+            Shader::load_matching(&config, "post/[*.wgsl"),
+            Err(SourceError::Pattern(_))
+        ));
+    }
+
+    #[test]
+    fn transpile_errors_can_be_collected() {
+        fn owned<T: std::error::Error + 'static>(_: &T) {}
+
+        let errors: Vec<TranspileError> = vec![
+            TranspileError::NoEntryPoint,
+            TranspileError::TargetNotSupported,
+        ];
+        errors.iter().for_each(owned);
+        let boxed: Box<dyn std::error::Error> = Box::new(TranspileError::NoEntryPoint);
+        assert_eq!(boxed.to_string(), "shader has no entry point");
+    }
+
+    #[test]
+    #[cfg(all(feature = "glsl-in", feature = "wgsl-out", feature = "spv-out"))]
+    fn intermediate_wgsl_is_dumped() {
+        let root = TempDir::new("starch-intermediate-wgsl").unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(
+            root.join("src/fill.frag"),
+            "#version 450\n\
+             layout(location = 0) out vec4 color;\n\
+             void main() {\n    color = vec4(1.0);\n}\n",
+        )
+        .unwrap();
+        let config = StarchConfig {
+            targets: vec![ShaderLanguage::SPV],
+            dump_intermediate_wgsl: true,
+            ..StarchConfig::init(&root)
+        };
+
+        let shaders = Shader::load_shaders(&config).unwrap();
+        let result = shaders.transpile_and_write(&config).unwrap();
+        let dumped = config
+            .target_dir(ShaderLanguage::WGSL)
+            .join("fill.frag.debug.wgsl");
+        assert!(std::fs::read_to_string(dumped)
+            .unwrap()
+            .contains("@fragment"));
+        assert!(result.includes[ShaderLanguage::WGSL as usize].is_empty());
+        assert_eq!(result.includes[ShaderLanguage::SPV as usize].len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "glsl-in")]
+    fn compute_local_size_defines_are_resolved() {
+        let root = TempDir::new("starch-local-size").unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(
+            root.join("src/clear.comp"),
+            "#version 450\n\
+             layout(local_size_x = GROUP_SIZE) in;\n\
+             layout(set = 0, binding = 0) buffer Data { uint values[]; };\n\
+             void main() {\n    values[gl_GlobalInvocationID.x] = 0u;\n}\n",
+        )
+        .unwrap();
+        let mut config = StarchConfig {
+            targets: vec![],
+            ..StarchConfig::init(&root)
+        };
+        config
+            .defines
+            .insert("GROUP_SIZE".to_string(), "32".to_string());
+
+        let shaders = Shader::load_shaders(&config).unwrap();
+        let reflection = shaders[0].reflect().unwrap();
+        assert_eq!(reflection.entry_points[0].workgroup_size, Some([32, 1, 1]));
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "wgsl-in",
+        feature = "glsl-out",
+        not(feature = "web-glsl-out")
+    ))]
+    fn entry_points_are_filtered_by_stage() {
+        let root = TempDir::new("starch-stage-filter").unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(
+            root.join("src/mixed.wgsl"),
+            "@vertex\n\
+             fn vs_main() -> @builtin(position) vec4<f32> {\n    return vec4<f32>(0.0);\n}\n\
+             @compute @workgroup_size(8)\n\
+             fn cs_main() {}\n",
+        )
+        .unwrap();
+        let config = StarchConfig {
+            targets: vec![ShaderLanguage::GLSL],
+            stages: Some(vec![naga::ShaderStage::Compute]),
+            ..StarchConfig::init(&root)
+        };
+
+        let shaders = Shader::load_shaders(&config).unwrap();
+        assert_eq!(shaders[0].selected_entry_points().len(), 1);
+        let result = shaders.transpile_and_write(&config).unwrap();
+        let stages: Vec<_> = result.iter_results().map(|(_, it)| it.stage).collect();
+        assert_eq!(stages, vec![Some(naga::ShaderStage::Compute)]);
+    }
+
+    #[test]
+    fn missing_source_dir_is_reported() {
+        let root = TempDir::new("starch-missing-src").unwrap();
+        let config = StarchConfig {
+            src: vec![root.join("shaders")],
+            ..StarchConfig::init(&root)
+        };
+
+        match Shader::load_shaders(&config) {
+            Err(SourceError::SourceDirMissing(path)) => {
+                assert_eq!(path, root.join("shaders"))
+            }
+            other => panic!("expected missing source dir, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "wgsl-in", feature = "wgsl-out"))]
+    fn stale_outputs_are_checked_without_writing() {
+        let root = crate::util::TempDir::new("starch-check-outputs").unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::copy(
+            "./test/src/double.wgsl",
+            root.join("src").join("double.wgsl"),
+        )
+        .unwrap();
+        let config = StarchConfig {
+            targets: vec![ShaderLanguage::WGSL],
+            ..StarchConfig::from_env(&root)
+        };
+        let output = config
+            .target_dir(ShaderLanguage::WGSL)
+            .join("double.comp.wgsl");
+
+        let shaders = Shader::load_shaders(&config).unwrap();
+        let (_, stale) = shaders.check_outputs(&config).unwrap();
+        assert_eq!(stale, vec![output.clone()]);
+        assert!(!config.out.exists());
+
+        let data = shaders.transpile_and_write(&config).unwrap();
+        data.generate_sources(&config).unwrap();
+        let (data, stale) = shaders.check_outputs(&config).unwrap();
+        assert!(stale.is_empty());
+        assert!(data.check_sources(&config).unwrap().is_empty());
+
+        std::fs::write(&output, "// edited").unwrap();
+        let leftover = config.target_dir(ShaderLanguage::WGSL).join("old.wgsl");
+        std::fs::write(&leftover, "").unwrap();
+        let (_, stale) = shaders.check_outputs(&config).unwrap();
+        assert_eq!(stale, vec![output.clone(), leftover]);
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "// edited");
     }
 }
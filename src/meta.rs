@@ -0,0 +1,100 @@
+#[cfg(feature = "config-file")]
+use crate::error::ParseLanguageError;
+use crate::language::transpile::ShaderLanguage;
+use crate::source::SourceProvider;
+#[cfg(feature = "config-file")]
+use crate::util::StageExt;
+use naga::ShaderStage;
+#[cfg(feature = "config-file")]
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "config-file")]
+use std::str::FromStr;
+
+/// Per-shader settings read from a `<shader file>.meta` sidecar file.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "config-file", derive(Deserialize))]
+#[cfg_attr(feature = "config-file", serde(default))]
+pub struct ShaderMeta {
+    #[cfg_attr(feature = "config-file", serde(deserialize_with = "deserialize_stage"))]
+    pub stage: Option<ShaderStage>,
+    /// Replaces `Config::targets` for this shader.
+    #[cfg_attr(
+        feature = "config-file",
+        serde(deserialize_with = "deserialize_targets")
+    )]
+    pub targets: Option<Vec<ShaderLanguage>>,
+    /// Define values, every combination of which is output as a separate
+    /// variant. Only supported for GLSL sources.
+    pub variants: BTreeMap<String, Vec<String>>,
+    /// Values baked into module constants, keyed by constant name or
+    /// specialization ID.
+    pub overrides: BTreeMap<String, f64>,
+}
+
+#[cfg(feature = "config-file")]
+fn deserialize_stage<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ShaderStage>, D::Error> {
+    let name: Option<String> = Option::deserialize(deserializer)?;
+    name.map(|name| {
+        ShaderStage::from_name(&name).ok_or_else(|| {
+            serde::de::Error::custom(format!("unknown shader stage: {}", name))
+        })
+    })
+    .transpose()
+}
+
+#[cfg(feature = "config-file")]
+fn deserialize_targets<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<ShaderLanguage>>, D::Error> {
+    let names: Option<Vec<String>> = Option::deserialize(deserializer)?;
+    let names = match names {
+        Some(names) => names,
+        None => return Ok(None),
+    };
+
+    let mut result = Vec::with_capacity(names.len());
+    for name in names {
+        match ShaderLanguage::from_str(&name) {
+            Ok(lang) => result.push(lang),
+            Err(ParseLanguageError::FeatureDisabled(lang)) => {
+                log::warn!(
+                    "Ignoring {} target: {}",
+                    lang,
+                    ParseLanguageError::FeatureDisabled(lang)
+                );
+            }
+            Err(err) => return Err(serde::de::Error::custom(err)),
+        }
+    }
+    Ok(Some(result))
+}
+
+impl ShaderMeta {
+    pub fn path_for(shader_path: impl AsRef<Path>) -> PathBuf {
+        let mut path = OsString::from(shader_path.as_ref());
+        path.push(".meta");
+        PathBuf::from(path)
+    }
+
+    #[cfg(feature = "config-file")]
+    pub fn load(provider: &dyn SourceProvider, path: &Path) -> Option<ShaderMeta> {
+        let contents = provider.read(path).ok()?;
+        match serde_yaml::from_slice(&contents) {
+            Ok(meta) => Some(meta),
+            Err(err) => {
+                log::error!("invalid shader metadata {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    #[cfg(not(feature = "config-file"))]
+    pub fn load(_provider: &dyn SourceProvider, _path: &Path) -> Option<ShaderMeta> {
+        None
+    }
+}
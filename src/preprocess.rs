@@ -1,44 +1,904 @@
-use crate::config::Config;
+use crate::config::{Config, IncludeSyntax};
+use crate::error::{IncludeLimit, PreprocessError, SourceError};
+use crate::language::transpile::ShaderLanguage;
 use crate::shader::{Shader, ShaderCode};
-use regex::Regex;
-use std::path::PathBuf;
-
-lazy_static::lazy_static! {
-    pub static ref INCLUDE_MACRO: Regex = {
-        let path_str = r"((\.|\.\.|[\w\d\-_\.]+)((\\|/)(\.\.|[\w\d\-_\.]+))*)";
-        let expected = format!("@starch::include \\s+('({0})'|\"({0})\")", path_str);
-        Regex::new(&expected).unwrap()
+use crate::source::{FileSystemProvider, SourceProvider};
+use crate::util::{normalize_path, StageExt};
+use naga::ShaderStage;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+mod matcher;
+
+pub use matcher::LiteralMatcher;
+
+pub const INCLUDE_DIRECTIVE: &str = "@starch::include";
+pub const C_INCLUDE_DIRECTIVE: &str = "#include";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanState {
+    Code,
+    LineComment,
+    BlockComment(usize),
+    Quoted(char),
+}
+
+/// Include directive found in shader source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Include<'a> {
+    range: Range<usize>,
+    path: &'a str,
+    /// `#include <path>`, only searched for in include paths.
+    system: bool,
+}
+
+/// Parses the quoted path following a `syntax` directive ending at `from`,
+/// returns the path, the end of the directive and whether the path is in angle
+/// brackets.
+fn parse_include_path(
+    source: &str,
+    from: usize,
+    syntax: IncludeSyntax,
+) -> Option<(&str, usize, bool)> {
+    let rest = &source[from..];
+    let trimmed = rest.trim_start_matches(|c: char| c.is_whitespace() && c != '\n');
+    if trimmed.len() == rest.len() {
+        return None;
+    }
+
+    let mut chars = trimmed.chars();
+    let open = chars.next()?;
+    let close = match (syntax, open) {
+        (_, '"') => '"',
+        (IncludeSyntax::Starch, '\'') => '\'',
+        (IncludeSyntax::C, '<') => '>',
+        _ => return None,
+    };
+    let path_len = chars.as_str().find([close, '\n'])?;
+    let path = &chars.as_str()[..path_len];
+    if path.is_empty() || !chars.as_str()[path_len..].starts_with(close) {
+        return None;
+    }
+
+    let path_start = source.len() - chars.as_str().len();
+    Some((path, path_start + path_len + close.len_utf8(), open == '<'))
+}
+
+/// Finds include directives in a single pass, skipping comments and quoted text.
+/// Block comments nest when `nested_comments` is set, as they do in WGSL.
+fn find_includes<'a>(
+    source: &'a str,
+    nested_comments: bool,
+    syntax: &[IncludeSyntax],
+) -> Vec<Include<'a>> {
+    let mut result = vec![];
+    let mut matchers: Vec<(IncludeSyntax, LiteralMatcher)> = syntax
+        .iter()
+        .map(|it| (*it, LiteralMatcher::new(it.directive())))
+        .collect();
+    let reset = |matchers: &mut Vec<(IncludeSyntax, LiteralMatcher)>| {
+        matchers.iter_mut().for_each(|(_, it)| it.reset())
     };
+    let mut state = ScanState::Code;
+    let mut chars = source.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|(_, c)| *c);
+        match state {
+            ScanState::Code => match (c, next) {
+                ('/', Some('/')) => {
+                    chars.next();
+                    state = ScanState::LineComment;
+                    reset(&mut matchers);
+                }
+                ('/', Some('*')) => {
+                    chars.next();
+                    state = ScanState::BlockComment(1);
+                    reset(&mut matchers);
+                }
+                ('"' | '\'', _) => {
+                    state = ScanState::Quoted(c);
+                    reset(&mut matchers);
+                }
+                _ => {
+                    // every matcher has to see every character
+                    let matched =
+                        matchers.iter_mut().fold(None, |found, (syntax, it)| {
+                            let done = it.next(c);
+                            found.or(done.then_some((*syntax, it.byte_len())))
+                        });
+                    let (syntax, directive_len) = match matched {
+                        Some(it) => it,
+                        None => continue,
+                    };
+                    reset(&mut matchers);
+
+                    let directive_end = i + c.len_utf8();
+                    let start = directive_end - directive_len;
+                    if let Some((path, end, system)) =
+                        parse_include_path(source, directive_end, syntax)
+                    {
+                        result.push(Include {
+                            range: start..end,
+                            path,
+                            system,
+                        });
+                        while chars.peek().is_some_and(|(j, _)| *j < end) {
+                            chars.next();
+                        }
+                    }
+                }
+            },
+            ScanState::LineComment => {
+                if c == '\n' {
+                    state = ScanState::Code;
+                }
+            }
+            ScanState::BlockComment(depth) => match (c, next) {
+                ('*', Some('/')) => {
+                    chars.next();
+                    state = match depth {
+                        1 => ScanState::Code,
+                        _ => ScanState::BlockComment(depth - 1),
+                    };
+                }
+                ('/', Some('*')) if nested_comments => {
+                    chars.next();
+                    state = ScanState::BlockComment(depth + 1);
+                }
+                _ => {}
+            },
+            ScanState::Quoted(quote) => {
+                if c == quote || c == '\n' {
+                    state = ScanState::Code;
+                }
+            }
+        }
+    }
+
+    result
+}
+
+pub const DIRECTIVE_PREFIX: &str = "starch:";
+
+/// Sets the stage of GLSL shaders without one in their file name from a
+/// glslang style `#pragma shader_stage(<stage>)`.
+fn apply_stage_pragma(shader: &mut Shader, source: &str) {
+    if shader.lang != ShaderLanguage::GLSL || shader.source_stage.is_some() {
+        return;
+    }
+
+    let stage = source.lines().find_map(|line| {
+        line.trim_start()
+            .strip_prefix('#')?
+            .trim_start()
+            .strip_prefix("pragma")?
+            .trim_start()
+            .strip_prefix("shader_stage")?
+            .trim_start()
+            .strip_prefix('(')?
+            .split_once(')')
+            .map(|(stage, _)| stage.trim())
+    });
+    if let Some(stage) = stage {
+        match ShaderStage::from_name(stage) {
+            Some(stage) => shader.source_stage = Some(stage),
+            None => log::warn!(
+                "{}: unsupported shader stage: {}",
+                shader.path.display(),
+                stage
+            ),
+        }
+    }
+}
+
+/// Applies `// starch: key[=value]` line comment directives to `shader`. List
+/// values are comma separated:
+///
+/// - `entry=<names>` only generates outputs for listed entry points,
+/// - `stage=<stage>` sets the source stage,
+/// - `skip` excludes the shader from parsing and transpilation,
+/// - `targets=<languages>` overrides `Config::targets`,
+/// - `name=<identifier>` overrides the name used in generated sources.
+fn apply_directives(shader: &mut Shader, source: &str) {
+    let directives = source.lines().filter_map(|line| {
+        line.trim_start()
+            .strip_prefix("//")?
+            .trim_start()
+            .strip_prefix(DIRECTIVE_PREFIX)
+            .map(str::trim)
+    });
+
+    for directive in directives {
+        let (key, value) = match directive.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim())),
+            None => (directive, None),
+        };
+        let list = || -> Vec<&str> {
+            value
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|it| !it.is_empty())
+                .collect()
+        };
+
+        match (key, value) {
+            ("entry", Some(_)) => {
+                shader.entry_names = Some(list().into_iter().map(String::from).collect());
+            }
+            ("stage", Some(value)) => match ShaderStage::from_name(value) {
+                Some(stage) => shader.source_stage = Some(stage),
+                None => log::warn!(
+                    "{}: unknown shader stage: {}",
+                    shader.path.display(),
+                    value
+                ),
+            },
+            ("skip", None) => shader.skip = true,
+            ("targets", Some(_)) => {
+                shader.targets = Some(
+                    list()
+                        .into_iter()
+                        .filter_map(|name| match ShaderLanguage::from_str(name) {
+                            Ok(lang) => Some(lang),
+                            Err(err) => {
+                                log::warn!("{}: {}", shader.path.display(), err);
+                                None
+                            }
+                        })
+                        .collect(),
+                );
+            }
+            ("name", Some(value)) if !value.is_empty() => {
+                shader.name = Some(value.to_string());
+            }
+            _ => log::warn!(
+                "{}: invalid directive: {}",
+                shader.path.display(),
+                directive
+            ),
+        }
+    }
 }
 
-fn proc_includes(buffer: &mut str, _config: &Config) {
-    let mut includes: Vec<(usize, usize)> = vec![];
+/// Bounds on include expansion of a single shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IncludeLimits {
+    /// Maximum nesting depth of included files.
+    pub max_depth: usize,
+    /// Maximum size of the expanded source in bytes.
+    pub max_size: usize,
+}
+
+impl Default for IncludeLimits {
+    fn default() -> Self {
+        IncludeLimits {
+            max_depth: 32,
+            max_size: 16 * 1024 * 1024,
+        }
+    }
+}
 
-    while INCLUDE_MACRO.find(buffer).is_some() {
-        for captures in INCLUDE_MACRO.captures_iter(buffer) {
-            let whole = captures.get(0).unwrap();
-            let path = PathBuf::from(captures.get(1).unwrap().as_str());
-            log::debug!("found include path: {}", path.display());
+pub const PRAGMA_ONCE: &str = "#pragma once";
+
+/// Returns `source` without its `#pragma once` lines if it has any. Line breaks
+/// are kept so line numbers don't shift.
+fn strip_pragma_once(source: &str) -> Option<String> {
+    let mut found = false;
+    let stripped = source
+        .split_inclusive('\n')
+        .map(|line| {
+            let content = line.trim();
+            if content == PRAGMA_ONCE {
+                found = true;
+                &line[line.trim_end().len()..]
+            } else {
+                line
+            }
+        })
+        .collect();
+    found.then_some(stripped)
+}
+
+struct IncludeExpander<'a> {
+    provider: &'a dyn SourceProvider,
+    include_paths: &'a [PathBuf],
+    syntax: &'a [IncludeSyntax],
+    limits: IncludeLimits,
+    chain: Vec<PathBuf>,
+    dependencies: &'a mut Vec<PathBuf>,
+    /// Files marked with `#pragma once` that were already expanded.
+    once: Vec<PathBuf>,
+}
 
-            includes.push((whole.start(), whole.end()));
+impl IncludeExpander<'_> {
+    fn limit_exceeded(&self, limit: IncludeLimit, included: &Path) -> PreprocessError {
+        let mut chain = self.chain.clone();
+        chain.push(included.to_path_buf());
+        PreprocessError::IncludeLimitExceeded { limit, chain }
+    }
+
+    fn push(
+        &self,
+        out: &mut String,
+        text: &str,
+        path: &Path,
+    ) -> Result<(), PreprocessError> {
+        if out.len() + text.len() > self.limits.max_size {
+            return Err(
+                self.limit_exceeded(IncludeLimit::Size(self.limits.max_size), path)
+            );
+        }
+        out.push_str(text);
+        Ok(())
+    }
+
+    /// Finds the file an include in a file in `parent` refers to. The path is
+    /// resolved with `resolve_include` first and then in each of the include
+    /// paths in order. `system` includes are only looked up in the include paths.
+    fn find_include(
+        &self,
+        parent: &Path,
+        path: &str,
+        system: bool,
+    ) -> Result<PathBuf, PreprocessError> {
+        let mut searched = vec![];
+        if !system {
+            let local = resolve_include(parent, path);
+            if self.provider.exists(&local) {
+                return Ok(local);
+            }
+            searched.push(local);
+        }
+
+        for dir in self.include_paths {
+            let candidate = normalize_path(dir.join(path.trim_start_matches('/')));
+            if self.provider.exists(&candidate) {
+                // absolute so it reads the same from any source root
+                return Ok(candidate.canonicalize().unwrap_or(candidate));
+            }
+            searched.push(candidate);
         }
+        Err(PreprocessError::IncludeNotFound {
+            path: path.to_string(),
+            searched,
+        })
+    }
+
+    /// Appends `source` to `out`, expanding includes in a single pass.
+    fn expand(
+        &mut self,
+        source: &str,
+        path: &Path,
+        out: &mut String,
+    ) -> Result<(), PreprocessError> {
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut last = 0;
+
+        let nested_comments =
+            ShaderLanguage::from_file_name(path) == Some(ShaderLanguage::WGSL);
+        for include in find_includes(source, nested_comments, self.syntax) {
+            self.push(out, &source[last..include.range.start], path)?;
+            last = include.range.end;
+            let directive = &source[include.range.clone()];
+
+            let included = self.find_include(parent, include.path, include.system)?;
+            log::debug!("found include path: {}", included.display());
+
+            if self.chain.contains(&included) {
+                log::error!("recursive include: {}", included.display());
+                self.push(out, directive, path)?;
+                continue;
+            }
+            if self.chain.len() >= self.limits.max_depth {
+                return Err(self.limit_exceeded(
+                    IncludeLimit::Depth(self.limits.max_depth),
+                    &included,
+                ));
+            }
+
+            if self.once.contains(&included) {
+                continue;
+            }
+            if !self.dependencies.contains(&included) {
+                self.dependencies.push(included.clone());
+            }
+            let mut contents =
+                self.provider.read_to_string(&included).map_err(|err| {
+                    PreprocessError::IncludeRead {
+                        path: included.clone(),
+                        source: err,
+                    }
+                })?;
+            if let Some(stripped) = strip_pragma_once(&contents) {
+                contents = stripped;
+                self.once.push(included.clone());
+            }
+
+            self.chain.push(included.clone());
+            self.expand(&contents, &included, out)?;
+            self.chain.pop();
+        }
+
+        self.push(out, &source[last..], path)
     }
 }
 
+/// Resolves an include `path` found in a file in `parent`. Paths starting with
+/// `/` are relative to the source root instead of the including file.
+fn resolve_include(parent: &Path, path: &str) -> PathBuf {
+    match path.strip_prefix('/') {
+        Some(rooted) => normalize_path(rooted),
+        None => normalize_path(parent.join(path)),
+    }
+}
+
+/// Expands includes of the root shader `source` read from `path`.
+fn expand_includes(
+    source: &str,
+    path: &Path,
+    provider: &dyn SourceProvider,
+    include_paths: &[PathBuf],
+    syntax: &[IncludeSyntax],
+    dependencies: &mut Vec<PathBuf>,
+    limits: IncludeLimits,
+) -> Result<String, PreprocessError> {
+    let path = normalize_path(path);
+    let stripped = strip_pragma_once(source);
+    let source = stripped.as_deref().unwrap_or(source);
+
+    let mut expander = IncludeExpander {
+        provider,
+        include_paths,
+        syntax,
+        limits,
+        chain: vec![path.clone()],
+        dependencies,
+        once: vec![],
+    };
+    let mut expanded = String::with_capacity(source.len());
+    expander.expand(source, &path, &mut expanded)?;
+    Ok(expanded)
+}
+
 pub fn preprocess_shader<'a>(
     shader: &'a mut Shader,
     config: &'a Config,
-) -> Option<&'a ShaderCode> {
-    let full_path = config.src.join(&shader.path);
-    let mut result = ShaderCode::read(&full_path, shader.lang.is_binary()).ok()?;
+) -> Result<&'a ShaderCode, SourceError> {
+    let provider = if shader.root.as_os_str().is_empty() {
+        FileSystemProvider::from_config(config)
+    } else {
+        FileSystemProvider::for_root(config, &shader.root)
+    };
+    preprocess_shader_from(shader, config, &provider)
+}
+
+pub fn preprocess_shader_from<'a>(
+    shader: &'a mut Shader,
+    config: &'a Config,
+    provider: &dyn SourceProvider,
+) -> Result<&'a ShaderCode, SourceError> {
+    let mut result = provider
+        .read(&shader.path)
+        .and_then(|bytes| ShaderCode::from_bytes(bytes, shader.lang.is_binary()))
+        .map_err(|err| SourceError::Read(shader.path.clone(), err))?;
 
     match &mut result {
         ShaderCode::Text(value) => {
-            //proc_includes(value, config);
+            apply_stage_pragma(shader, value);
+            apply_directives(shader, value);
+
+            *value = expand_includes(
+                value,
+                &shader.path,
+                provider,
+                &config.include_paths,
+                &config.include_syntax,
+                &mut shader.dependencies,
+                config.include_limits(),
+            )
+            .map_err(|err| SourceError::Preprocess(shader.path.clone(), err))?;
         }
         ShaderCode::Binary(_) => {}
     }
 
-    shader.source = Some(result);
-    shader.source.as_ref()
+    Ok(shader.source.insert(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    struct MemoryProvider(HashMap<PathBuf, String>);
+
+    impl SourceProvider for MemoryProvider {
+        fn collect(&self, filter: &dyn Fn(&Path) -> bool) -> Vec<PathBuf> {
+            self.0.keys().filter(|path| filter(path)).cloned().collect()
+        }
+
+        fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+            self.0
+                .get(path)
+                .map(|text| text.as_bytes().to_vec())
+                .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+        }
+    }
+
+    fn expand(provider: &MemoryProvider, path: &str) -> (String, Vec<PathBuf>) {
+        let path = PathBuf::from(path);
+        let source = provider.read_to_string(&path).unwrap();
+        let mut dependencies = vec![];
+        let out = expand_includes(
+            &source,
+            &path,
+            provider,
+            &[],
+            &[IncludeSyntax::Starch],
+            &mut dependencies,
+            IncludeLimits::default(),
+        )
+        .unwrap();
+        (out, dependencies)
+    }
+
+    #[test]
+    fn includes_expand_in_place() {
+        let mut files = HashMap::new();
+        let mut main = String::new();
+        for i in 0..50 {
+            main.push_str(&"// filler\n".repeat(40));
+            main.push_str(&format!("@starch::include \"lib/part{}.wgsl\"\n", i));
+            files.insert(
+                PathBuf::from(format!("lib/part{}.wgsl", i)),
+                format!("@starch::include '../common.wgsl'\nfn part{}() {{}}\n", i),
+            );
+        }
+        files.insert(PathBuf::from("common.wgsl"), "// common\n".to_string());
+        files.insert(PathBuf::from("main.wgsl"), main);
+        let provider = MemoryProvider(files);
+
+        let (out, dependencies) = expand(&provider, "main.wgsl");
+
+        assert!(!out.contains("@starch::include"));
+        assert!(out.contains("fn part0() {}") && out.contains("fn part49() {}"));
+        assert_eq!(out.matches("// common").count(), 50);
+        assert_eq!(dependencies.len(), 51);
+    }
+
+    #[test]
+    fn pragma_once_includes_are_expanded_once() {
+        let mut files = HashMap::new();
+        files.insert(
+            PathBuf::from("a.wgsl"),
+            "#pragma once\n@starch::include 'b.wgsl'\n@starch::include 'c.wgsl'\n"
+                .to_string(),
+        );
+        files.insert(
+            PathBuf::from("b.wgsl"),
+            "@starch::include 'common.wgsl'\nfn b() {}\n".to_string(),
+        );
+        files.insert(
+            PathBuf::from("c.wgsl"),
+            "@starch::include 'common.wgsl'\nfn c() {}\n".to_string(),
+        );
+        files.insert(
+            PathBuf::from("common.wgsl"),
+            "  #pragma once\nfn common() {}\n".to_string(),
+        );
+        let provider = MemoryProvider(files);
+
+        let (out, _) = expand(&provider, "a.wgsl");
+
+        assert_eq!(out, "\n\nfn common() {}\n\nfn b() {}\n\n\nfn c() {}\n\n");
+
+        // skipped includes aren't read
+        struct Reads<'a>(&'a MemoryProvider, RefCell<Vec<PathBuf>>);
+        impl SourceProvider for Reads<'_> {
+            fn collect(&self, filter: &dyn Fn(&Path) -> bool) -> Vec<PathBuf> {
+                self.0.collect(filter)
+            }
+
+            fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+                self.1.borrow_mut().push(path.to_path_buf());
+                self.0.read(path)
+            }
+
+            fn exists(&self, path: &Path) -> bool {
+                self.0.exists(path)
+            }
+        }
+        let reads = Reads(&provider, RefCell::new(vec![]));
+        let source = provider.read_to_string(Path::new("a.wgsl")).unwrap();
+        expand_includes(
+            &source,
+            Path::new("a.wgsl"),
+            &reads,
+            &[],
+            &[IncludeSyntax::Starch],
+            &mut vec![],
+            IncludeLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            reads.1.into_inner(),
+            [
+                PathBuf::from("b.wgsl"),
+                PathBuf::from("common.wgsl"),
+                PathBuf::from("c.wgsl")
+            ]
+        );
+    }
+
+    #[test]
+    fn rooted_includes_resolve_from_source_root() {
+        let mut files = HashMap::new();
+        files.insert(
+            PathBuf::from("post/blur.wgsl"),
+            "@starch::include '/lib/noise.wgsl'\nfn blur() {}\n".to_string(),
+        );
+        files.insert(
+            PathBuf::from("lib/noise.wgsl"),
+            "@starch::include 'hash.wgsl'\nfn noise() {}\n".to_string(),
+        );
+        files.insert(PathBuf::from("lib/hash.wgsl"), "fn hash() {}\n".to_string());
+        let provider = MemoryProvider(files);
+
+        let (out, dependencies) = expand(&provider, "post/blur.wgsl");
+
+        assert_eq!(out, "fn hash() {}\n\nfn noise() {}\n\nfn blur() {}\n");
+        assert_eq!(
+            dependencies,
+            [
+                PathBuf::from("lib/noise.wgsl"),
+                PathBuf::from("lib/hash.wgsl")
+            ]
+        );
+    }
+
+    #[test]
+    fn include_paths_are_searched_in_order() {
+        let vendor = PathBuf::from("/vendor");
+        let mut files = HashMap::new();
+        files.insert(
+            PathBuf::from("main.wgsl"),
+            "@starch::include 'noise/simplex.wgsl'\n".to_string(),
+        );
+        files.insert(
+            vendor.join("noise").join("simplex.wgsl"),
+            "fn simplex() {}\n".to_string(),
+        );
+        let provider = MemoryProvider(files);
+        let source = provider.read_to_string(Path::new("main.wgsl")).unwrap();
+        let expand_with = |include_paths: &[PathBuf]| {
+            let mut dependencies = vec![];
+            expand_includes(
+                &source,
+                Path::new("main.wgsl"),
+                &provider,
+                include_paths,
+                &[IncludeSyntax::Starch],
+                &mut dependencies,
+                IncludeLimits::default(),
+            )
+            .map(|out| (out, dependencies))
+        };
+
+        let missing = vendor.join("missing");
+        let (out, dependencies) =
+            expand_with(&[missing.clone(), vendor.clone()]).unwrap();
+        assert_eq!(out, "fn simplex() {}\n\n");
+        assert_eq!(dependencies, [vendor.join("noise").join("simplex.wgsl")]);
+
+        match expand_with(std::slice::from_ref(&missing)) {
+            Err(PreprocessError::IncludeNotFound { path, searched }) => {
+                assert_eq!(path, "noise/simplex.wgsl");
+                assert_eq!(
+                    searched,
+                    [
+                        PathBuf::from("noise/simplex.wgsl"),
+                        missing.join("noise").join("simplex.wgsl")
+                    ]
+                );
+            }
+            other => panic!("expected missing include, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn c_includes_are_expanded_when_enabled() {
+        let vendor = PathBuf::from("/vendor");
+        let mut files = HashMap::new();
+        files.insert(
+            PathBuf::from("main.frag"),
+            "#include \"common.glsl\"\n#include <common.glsl>\n\
+             @starch::include 'common.glsl'\n"
+                .to_string(),
+        );
+        files.insert(PathBuf::from("common.glsl"), "float local();\n".to_string());
+        files.insert(
+            vendor.join("common.glsl"),
+            "float vendored();\n".to_string(),
+        );
+        let provider = MemoryProvider(files);
+        let source = provider.read_to_string(Path::new("main.frag")).unwrap();
+        let expand_with = |syntax: &[IncludeSyntax]| {
+            expand_includes(
+                &source,
+                Path::new("main.frag"),
+                &provider,
+                std::slice::from_ref(&vendor),
+                syntax,
+                &mut vec![],
+                IncludeLimits::default(),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            expand_with(&[IncludeSyntax::C]),
+            "float local();\n\nfloat vendored();\n\n@starch::include 'common.glsl'\n"
+        );
+        assert_eq!(
+            expand_with(&[IncludeSyntax::Starch, IncludeSyntax::C]),
+            "float local();\n\nfloat vendored();\n\nfloat local();\n\n"
+        );
+        assert_eq!(
+            expand_with(&[IncludeSyntax::Starch]),
+            "#include \"common.glsl\"\n#include <common.glsl>\nfloat local();\n\n"
+        );
+    }
+
+    #[test]
+    fn include_limits_are_enforced() {
+        let mut files = HashMap::new();
+        for i in 0..4 {
+            files.insert(
+                PathBuf::from(format!("{}.wgsl", i)),
+                format!("@starch::include '{}.wgsl'\nfn f{}() {{}}\n", i + 1, i),
+            );
+        }
+        files.insert(PathBuf::from("4.wgsl"), "fn f4() {}\n".to_string());
+        let provider = MemoryProvider(files);
+        let source = provider.read_to_string(Path::new("0.wgsl")).unwrap();
+        let expand_with = |limits| {
+            expand_includes(
+                &source,
+                Path::new("0.wgsl"),
+                &provider,
+                &[],
+                &[IncludeSyntax::Starch],
+                &mut vec![],
+                limits,
+            )
+        };
+
+        let shallow = IncludeLimits {
+            max_depth: 3,
+            ..Default::default()
+        };
+        match expand_with(shallow) {
+            Err(PreprocessError::IncludeLimitExceeded { limit, chain }) => {
+                assert_eq!(limit, IncludeLimit::Depth(3));
+                assert_eq!(chain.len(), 4);
+                assert_eq!(chain[3], PathBuf::from("3.wgsl"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let small = IncludeLimits {
+            max_size: 32,
+            ..Default::default()
+        };
+        assert!(matches!(
+            expand_with(small),
+            Err(PreprocessError::IncludeLimitExceeded {
+                limit: IncludeLimit::Size(32),
+                ..
+            })
+        ));
+
+        assert!(expand_with(IncludeLimits::default())
+            .unwrap()
+            .contains("fn f4() {}"));
+    }
+
+    #[test]
+    fn recursive_include_is_not_expanded() {
+        let mut files = HashMap::new();
+        files.insert(
+            PathBuf::from("a.wgsl"),
+            "@starch::include 'b.wgsl'".to_string(),
+        );
+        files.insert(
+            PathBuf::from("b.wgsl"),
+            "@starch::include 'a.wgsl'".to_string(),
+        );
+        let provider = MemoryProvider(files);
+
+        let (out, _) = expand(&provider, "a.wgsl");
+
+        assert_eq!(out, "@starch::include 'a.wgsl'");
+    }
+
+    #[test]
+    fn includes_in_comments_are_ignored() {
+        let source = "// @starch::include 'a.wgsl'\n\
+                      /* @starch::include 'b.wgsl'\n\
+                      @starch::include 'c.wgsl' */\n\
+                      let s = \"@starch::include 'd.wgsl'\";\n\
+                      @starch::include \"e.wgsl\"\n";
+
+        let paths: Vec<&str> = find_includes(source, false, &[IncludeSyntax::Starch])
+            .iter()
+            .map(|it| it.path)
+            .collect();
+
+        assert_eq!(paths, vec!["e.wgsl"]);
+    }
+
+    #[test]
+    #[cfg(feature = "wgsl-in")]
+    fn commented_includes_are_kept_as_is() {
+        let mut files = HashMap::new();
+        files.insert(
+            PathBuf::from("main.wgsl"),
+            "/* outer /* inner */ @starch::include 'a.wgsl' */\n\
+             // @starch::include 'a.wgsl'\n\
+             @starch::include 'a.wgsl'\n"
+                .to_string(),
+        );
+        files.insert(PathBuf::from("a.wgsl"), "fn a() {}".to_string());
+        let provider = MemoryProvider(files);
+
+        let (out, dependencies) = expand(&provider, "main.wgsl");
+
+        assert_eq!(
+            out,
+            "/* outer /* inner */ @starch::include 'a.wgsl' */\n\
+             // @starch::include 'a.wgsl'\n\
+             fn a() {}\n"
+        );
+        assert_eq!(dependencies, vec![PathBuf::from("a.wgsl")]);
+    }
+
+    #[test]
+    #[cfg(feature = "wgsl-in")]
+    fn directives_are_applied() {
+        let mut shader = Shader::new("shader.wgsl").unwrap();
+        apply_directives(
+            &mut shader,
+            "// starch: entry=vs_main, fs_main\n\
+             //starch: stage=fragment\n\
+             // starch: name=Blit\n\
+             // starch: skip\n\
+             fn main() {} // starch: name=ignored\n",
+        );
+
+        assert_eq!(
+            shader.entry_names,
+            Some(vec!["vs_main".to_string(), "fs_main".to_string()])
+        );
+        assert_eq!(shader.source_stage, Some(naga::ShaderStage::Fragment));
+        assert_eq!(shader.name.as_deref(), Some("Blit"));
+        assert!(shader.skip);
+    }
+
+    #[test]
+    #[cfg(feature = "glsl-in")]
+    fn glsl_stage_pragma_is_applied() {
+        let source = "#version 450\n#pragma shader_stage( fragment )\nvoid main() {}\n";
+
+        let mut plain = Shader::new("model.glsl").unwrap();
+        apply_stage_pragma(&mut plain, source);
+        assert_eq!(plain.source_stage, Some(naga::ShaderStage::Fragment));
+
+        let mut staged = Shader::new("model.vs.glsl").unwrap();
+        staged.source_stage = Some(naga::ShaderStage::Vertex);
+        apply_stage_pragma(&mut staged, source);
+        assert_eq!(staged.source_stage, Some(naga::ShaderStage::Vertex));
+    }
 }
@@ -0,0 +1,99 @@
+/// Incrementally matches a literal against a stream of characters.
+#[derive(Debug, Clone)]
+pub struct LiteralMatcher {
+    target: Vec<char>,
+    byte_len: usize,
+    // length of the longest proper prefix of target[..=i] that is also its suffix
+    fallback: Vec<usize>,
+    pos: usize,
+}
+
+impl LiteralMatcher {
+    pub fn new(target: &str) -> LiteralMatcher {
+        let target: Vec<char> = target.chars().collect();
+        let mut fallback = vec![0; target.len()];
+        let mut matched = 0;
+        for i in 1..target.len() {
+            while matched > 0 && target[i] != target[matched] {
+                matched = fallback[matched - 1];
+            }
+            if target[i] == target[matched] {
+                matched += 1;
+            }
+            fallback[i] = matched;
+        }
+
+        LiteralMatcher {
+            byte_len: target.iter().map(|c| c.len_utf8()).sum(),
+            target,
+            fallback,
+            pos: 0,
+        }
+    }
+
+    /// Advances the matcher by `c`, returns `true` if it completed the target.
+    pub fn next(&mut self, c: char) -> bool {
+        if self.target.is_empty() {
+            return true;
+        }
+        if self.is_done() {
+            self.pos = self.fallback[self.pos - 1];
+        }
+
+        while self.pos > 0 && self.target[self.pos] != c {
+            self.pos = self.fallback[self.pos - 1];
+        }
+        if self.target[self.pos] == c {
+            self.pos += 1;
+        }
+        self.is_done()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pos == self.target.len()
+    }
+
+    pub fn reset(&mut self) {
+        self.pos = 0;
+    }
+
+    /// Length of the target in bytes.
+    pub fn byte_len(&self) -> usize {
+        self.byte_len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn match_ends(target: &str, text: &str) -> Vec<usize> {
+        let mut matcher = LiteralMatcher::new(target);
+        text.char_indices()
+            .filter(|(_, c)| matcher.next(*c))
+            .map(|(i, c)| i + c.len_utf8())
+            .collect()
+    }
+
+    #[test]
+    fn matches_literal() {
+        assert_eq!(match_ends("use", "fn use() use"), vec![6, 12]);
+        assert_eq!(match_ends("aab", "aaab"), vec![4]);
+        assert_eq!(match_ends("aa", "aaaa"), vec![2, 3, 4]);
+        assert!(match_ends("use", "us e").is_empty());
+    }
+
+    #[test]
+    fn matches_multi_byte_literal() {
+        assert_eq!(LiteralMatcher::new("#içlüde").byte_len(), 9);
+        assert_eq!(match_ends("#içlüde", "é #içlüde #içlüde"), vec![12, 22]);
+        assert_eq!(match_ends("ßß", "ßßß"), vec![4, 6]);
+        assert!(match_ends("#içlüde", "#iclude #içlude").is_empty());
+
+        let mut matcher = LiteralMatcher::new("ü");
+        assert!(matcher.next('ü'));
+        assert!(matcher.is_done());
+        assert!(!matcher.next('u'));
+        assert!(!matcher.is_done());
+    }
+}
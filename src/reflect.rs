@@ -0,0 +1,162 @@
+use crate::layout::{self, StructLayout};
+use naga::valid::ModuleInfo;
+use naga::{AddressSpace, ImageClass, Module, ShaderStage, StorageAccess, TypeInner};
+#[cfg(feature = "config-file")]
+use serde::Serialize;
+
+/// Everything a validated module exposes to the code using it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config-file", derive(Serialize))]
+pub struct ShaderReflection {
+    pub entry_points: Vec<EntryPointReflection>,
+    /// Bound resources, ordered by group and binding.
+    pub bindings: Vec<BindingReflection>,
+    /// Layouts of structs used by buffers.
+    pub structs: Vec<StructLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "config-file", derive(Serialize))]
+pub struct EntryPointReflection {
+    pub name: String,
+    pub stage: ShaderStage,
+    /// Workgroup size of compute entry points.
+    pub workgroup_size: Option<[u32; 3]>,
+    /// Indices into `ShaderReflection::bindings` of resources the entry point
+    /// uses.
+    pub bindings: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "config-file", derive(Serialize))]
+pub struct BindingReflection {
+    /// Name of the global variable.
+    pub name: Option<String>,
+    pub group: u32,
+    pub binding: u32,
+    pub kind: ResourceKind,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "config-file", derive(Serialize))]
+pub enum ResourceKind {
+    UniformBuffer,
+    StorageBuffer { writable: bool },
+    Texture,
+    DepthTexture,
+    StorageTexture { writable: bool },
+    Sampler,
+    ComparisonSampler,
+}
+
+pub fn reflect(module: &Module, info: &ModuleInfo) -> ShaderReflection {
+    let mut globals: Vec<_> = module
+        .global_variables
+        .iter()
+        .filter_map(|(handle, global)| {
+            let binding = global.binding.as_ref()?;
+            let kind = resource_kind(module, global.space, global.ty)?;
+            Some((
+                handle,
+                BindingReflection {
+                    name: global.name.clone(),
+                    group: binding.group,
+                    binding: binding.binding,
+                    kind,
+                },
+            ))
+        })
+        .collect();
+    globals.sort_by_key(|(_, it)| (it.group, it.binding));
+
+    let entry_points = module
+        .entry_points
+        .iter()
+        .enumerate()
+        .map(|(i, entry_point)| {
+            let uses = info.get_entry_point(i);
+            EntryPointReflection {
+                name: entry_point.name.clone(),
+                stage: entry_point.stage,
+                workgroup_size: (entry_point.stage == ShaderStage::Compute)
+                    .then_some(entry_point.workgroup_size),
+                bindings: globals
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (handle, _))| !uses[*handle].is_empty())
+                    .map(|(index, _)| index)
+                    .collect(),
+            }
+        })
+        .collect();
+
+    ShaderReflection {
+        entry_points,
+        bindings: globals.into_iter().map(|(_, it)| it).collect(),
+        structs: layout::buffer_structs(module),
+    }
+}
+
+fn resource_kind(
+    module: &Module,
+    space: AddressSpace,
+    ty: naga::Handle<naga::Type>,
+) -> Option<ResourceKind> {
+    Some(match space {
+        AddressSpace::Uniform => ResourceKind::UniformBuffer,
+        AddressSpace::Storage { access } => ResourceKind::StorageBuffer {
+            writable: access.contains(StorageAccess::STORE),
+        },
+        AddressSpace::Handle => match &module.types[ty].inner {
+            TypeInner::Image { class, .. } => match class {
+                ImageClass::Sampled { .. } => ResourceKind::Texture,
+                ImageClass::Depth { .. } => ResourceKind::DepthTexture,
+                ImageClass::Storage { access, .. } => ResourceKind::StorageTexture {
+                    writable: access.contains(StorageAccess::STORE),
+                },
+            },
+            TypeInner::Sampler { comparison: false } => ResourceKind::Sampler,
+            TypeInner::Sampler { comparison: true } => ResourceKind::ComparisonSampler,
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
+
+#[cfg(all(test, feature = "wgsl-in"))]
+mod tests {
+    use super::*;
+    use crate::shader::tests::parsed_fixture;
+    use crate::shader::Shader;
+
+    #[test]
+    fn shaders_can_be_reflected() {
+        let unparsed = Shader::new("./test/src/double.wgsl").unwrap();
+        assert!(unparsed.reflect().is_none());
+
+        let shader = parsed_fixture("./test/src/double.wgsl");
+        let reflection = shader.reflect().unwrap();
+        assert_eq!(
+            reflection.entry_points,
+            vec![EntryPointReflection {
+                name: "cs_main".to_string(),
+                stage: ShaderStage::Compute,
+                workgroup_size: Some([64, 1, 1]),
+                bindings: vec![0],
+            }]
+        );
+        assert_eq!(
+            reflection.bindings,
+            vec![BindingReflection {
+                name: Some("data".to_string()),
+                group: 0,
+                binding: 0,
+                kind: ResourceKind::StorageBuffer { writable: true },
+            }]
+        );
+        #[cfg(feature = "config-file")]
+        assert!(serde_yaml::to_string(&reflection)
+            .unwrap()
+            .contains("name: cs_main"));
+    }
+}
@@ -1,12 +1,23 @@
-use crate::config::Config;
-use crate::error::SourceError;
+use crate::config::{BoundsCheck, Config, GlslOptions, ValidationPreset};
+use crate::error::{SourceError, TranspileError};
+use crate::language::transpile::transpile_entry;
+use crate::layout::{self, StructLayout};
+use crate::meta::ShaderMeta;
 use crate::prelude_build::ShaderLanguage;
 use crate::preprocess;
-use crate::util::{collect_files, PathExt};
-use log::error;
-use naga::valid::ModuleInfo;
-use naga::{Module, ShaderStage};
+use crate::reflect::{self, ShaderReflection};
+use crate::source::{FileSystemProvider, SourceProvider};
+use crate::util::{
+    file_prefix, normalize_path, sanitize_identifier, stage_span, PathExt, StageExt,
+};
+use globset::GlobBuilder;
+use naga::valid::{ModuleInfo, ValidationError, Validator};
+use naga::{ConstantInner, EntryPoint, Module, ScalarValue, ShaderStage, WithSpan};
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::Write;
+use std::ops::Deref;
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone)]
@@ -39,13 +50,25 @@ impl AsMut<[u8]> for ShaderCode {
     }
 }
 
+/// Appends to the code. `Text` has nowhere to keep a partial character, so every
+/// write has to consist of whole UTF-8 sequences, like ones of `write_str`, and
+/// fails with `InvalidData` otherwise.
 impl Write for ShaderCode {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.as_mut().write(buf)
+        match self {
+            ShaderCode::Text(text) => {
+                let value = std::str::from_utf8(buf).map_err(|err| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+                })?;
+                text.push_str(value);
+                Ok(buf.len())
+            }
+            ShaderCode::Binary(bin) => bin.write(buf),
+        }
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        self.as_mut().flush()
+        Ok(())
     }
 }
 
@@ -60,18 +83,52 @@ impl std::fmt::Write for ShaderCode {
     }
 }
 
+impl std::fmt::Display for ShaderCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.as_text_lossy())
+    }
+}
+
 impl ShaderCode {
     pub fn read(
         path: impl AsRef<Path>,
         binary: bool,
+    ) -> Result<ShaderCode, std::io::Error> {
+        ShaderCode::from_bytes(std::fs::read(path.as_ref())?, binary)
+    }
+
+    /// Reads `path` as binary or text depending on `lang`.
+    pub fn from_path(
+        path: impl AsRef<Path>,
+        lang: ShaderLanguage,
+    ) -> Result<ShaderCode, std::io::Error> {
+        ShaderCode::read(path, lang.is_binary())
+    }
+
+    pub fn from_bytes(
+        bytes: Vec<u8>,
+        binary: bool,
     ) -> Result<ShaderCode, std::io::Error> {
         Ok(if binary {
-            ShaderCode::Binary(std::fs::read(path.as_ref())?)
+            ShaderCode::Binary(bytes)
         } else {
-            ShaderCode::Text(std::fs::read_to_string(path.as_ref())?)
+            ShaderCode::Text(String::from_utf8(bytes).map_err(|err| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+            })?)
         })
     }
 
+    pub fn len(&self) -> usize {
+        match self {
+            ShaderCode::Text(text) => text.len(),
+            ShaderCode::Binary(bin) => bin.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn get_text(&self) -> Option<&String> {
         match self {
             ShaderCode::Text(text) => Some(text),
@@ -100,6 +157,28 @@ impl ShaderCode {
         }
     }
 
+    /// Text content as is, binary content as a hex dump of 32-bit words, eight
+    /// per line.
+    pub fn as_text_lossy(&self) -> Cow<'_, str> {
+        match self {
+            ShaderCode::Text(text) => Cow::Borrowed(text),
+            ShaderCode::Binary(bin) => {
+                use std::fmt::Write as _;
+
+                let mut result = String::with_capacity(bin.len() * 9 / 4);
+                for (i, word) in bin.chunks(4).enumerate() {
+                    if i > 0 {
+                        result.push(if i % 8 == 0 { '\n' } else { ' ' });
+                    }
+                    for byte in word {
+                        let _ = write!(result, "{:02x}", byte);
+                    }
+                }
+                Cow::Owned(result)
+            }
+        }
+    }
+
     pub fn unwrap_text(&self) -> &str {
         self.get_text().unwrap()
     }
@@ -120,9 +199,39 @@ impl ShaderCode {
 #[derive(Debug)]
 pub struct Shader {
     pub path: PathBuf,
+    /// Source root `path` is relative to. Set for shaders loaded from configured
+    /// sources, empty for ones created directly, which resolve against the
+    /// primary root.
+    pub root: PathBuf,
     pub lang: ShaderLanguage,
     pub source_stage: Option<ShaderStage>,
+    /// Overrides `Config::targets` for this shader.
+    pub targets: Option<Vec<ShaderLanguage>>,
+    /// Entry points outputs are generated for, all of them if unset.
+    pub entry_names: Option<Vec<String>>,
+    /// Overrides the file name based identifier in generated sources.
+    pub name: Option<String>,
+    /// Excludes the shader from parsing and transpilation.
+    pub skip: bool,
+    /// Overrides emitted entry point names, see `Config::entry_point_name`.
+    pub entry_point_name: Option<String>,
+    /// Bounds checks added to generated code, see `Config::bounds_check`.
+    pub bounds_check: BoundsCheck,
+    /// Options of GLSL outputs, see `Config::glsl`.
+    pub glsl: GlslOptions,
+    /// Stages of entry points outputs are generated for, see `Config::stages`.
+    pub stages: Option<Vec<ShaderStage>>,
+    /// Preprocessor defines GLSL sources are parsed with.
+    pub defines: BTreeMap<String, String>,
+    /// Define values every combination of which is output as a separate variant.
+    pub variants: BTreeMap<String, Vec<String>>,
+    /// Suffix outputs of this variant are named with.
+    pub variant: Option<String>,
+    /// Values baked into module constants after parsing, keyed by constant name
+    /// or specialization ID.
+    pub overrides: BTreeMap<String, f64>,
     pub source: Option<ShaderCode>,
+    pub dependencies: Vec<PathBuf>,
 
     pub module: Option<Module>,
     pub module_info: Option<ModuleInfo>,
@@ -132,44 +241,240 @@ impl Shader {
     pub fn new(path: impl AsRef<Path>) -> Option<Shader> {
         Some(Shader {
             path: path.as_ref().to_path_buf(),
+            root: PathBuf::new(),
             lang: ShaderLanguage::from_file_name(path.as_ref())?,
             source_stage: stage_from_name(path.as_ref()),
+            targets: None,
+            entry_names: None,
+            name: None,
+            skip: false,
+            entry_point_name: None,
+            bounds_check: BoundsCheck::default(),
+            glsl: GlslOptions::default(),
+            stages: None,
+            defines: BTreeMap::new(),
+            variants: BTreeMap::new(),
+            variant: None,
+            overrides: BTreeMap::new(),
             source: None,
+            dependencies: vec![],
 
             module: None,
             module_info: None,
         })
     }
 
-    fn collect(config: &Config) -> Vec<Shader> {
-        collect_files(&config.src, |c| {
-            if c.is_dir() {
-                Some(c.to_path_buf()) != config.out.canonicalize().ok()
-            } else {
-                ShaderLanguage::from_file_name(c).is_some()
-            }
-        })
-        .into_iter()
-        .filter_map(Shader::new)
-        .collect()
+    pub(crate) fn collect(provider: &dyn SourceProvider) -> Vec<Shader> {
+        Shader::collect_selected(provider, &|_| true)
     }
 
-    pub fn load_shaders(config: &Config) -> Result<Vec<Shader>, SourceError> {
-        let mut result: Vec<Shader> = Shader::collect(config)
+    /// Collects shaders whose path relative to the provider root is accepted by
+    /// `select`.
+    fn collect_selected(
+        provider: &dyn SourceProvider,
+        select: &dyn Fn(&Path) -> bool,
+    ) -> Vec<Shader> {
+        provider
+            .collect(&|path| ShaderLanguage::from_file_name(path).is_some())
             .into_iter()
+            .filter(|path| select(path))
+            .filter_map(Shader::new)
             .map(|mut shader| {
-                preprocess::preprocess_shader(&mut shader, config);
+                let meta_path = ShaderMeta::path_for(&shader.path);
+                if let Some(meta) = ShaderMeta::load(provider, &meta_path) {
+                    shader.apply_meta(meta);
+                    shader.dependencies.push(meta_path);
+                }
                 shader
             })
-            .collect();
+            .collect()
+    }
+
+    pub fn apply_meta(&mut self, meta: ShaderMeta) {
+        if meta.stage.is_some() {
+            self.source_stage = meta.stage;
+        }
+        if meta.targets.is_some() {
+            self.targets = meta.targets;
+        }
+        if !meta.overrides.is_empty() {
+            self.overrides = meta.overrides;
+        }
+        let mut variants = meta.variants;
+        variants.retain(|define, values| {
+            if values.is_empty() {
+                log::warn!(
+                    "Ignoring variant define {} of {}: no values listed",
+                    define,
+                    self.path.display()
+                );
+            }
+            !values.is_empty()
+        });
+        if !variants.is_empty() {
+            self.variants = variants;
+            // the shader itself is parsed as its first variant
+            if let Some((_, defines)) = self.variant_defines().into_iter().next() {
+                self.defines = defines;
+            }
+        }
+    }
 
+    /// Defines of every combination of `variants` values, along with the
+    /// suffix outputs of that combination are named with.
+    pub fn variant_defines(&self) -> Vec<(String, BTreeMap<String, String>)> {
+        let mut result = vec![(String::new(), self.defines.clone())];
+        for (define, values) in &self.variants {
+            result = result
+                .into_iter()
+                .flat_map(|(suffix, defines)| {
+                    values.iter().map(move |value| {
+                        let part = sanitize_identifier(&format!("{}_{}", define, value))
+                            .to_ascii_lowercase();
+                        let mut defines = defines.clone();
+                        defines.insert(define.clone(), value.clone());
+                        if suffix.is_empty() {
+                            (part, defines)
+                        } else {
+                            (format!("{}_{}", suffix, part), defines)
+                        }
+                    })
+                })
+                .collect();
+        }
+        result
+    }
+
+    /// Parses and validates a copy of the shader for each of its variants.
+    pub fn build_variants(
+        &self,
+        config: &Config,
+        validator: &mut Validator,
+    ) -> Result<Vec<Shader>, SourceError> {
+        let mut result = Vec::new();
+        for (suffix, defines) in self.variant_defines() {
+            let mut variant = Shader {
+                path: self.path.clone(),
+                root: self.root.clone(),
+                lang: self.lang,
+                source_stage: self.source_stage,
+                targets: self.targets.clone(),
+                entry_names: self.entry_names.clone(),
+                name: self.name.clone(),
+                skip: false,
+                entry_point_name: self.entry_point_name.clone(),
+                bounds_check: self.bounds_check,
+                glsl: self.glsl.clone(),
+                stages: self.stages.clone(),
+                defines,
+                variants: BTreeMap::new(),
+                variant: Some(suffix),
+                overrides: self.overrides.clone(),
+                source: self.source.clone(),
+                dependencies: self.dependencies.clone(),
+
+                module: None,
+                module_info: None,
+            };
+            variant.parse()?;
+            variant.validate_for(config, validator)?;
+            result.push(variant);
+        }
+        Ok(result)
+    }
+
+    pub fn load_shaders(config: &Config) -> Result<Shaders, SourceError> {
+        Shader::load_selected(config, &|_| true)
+    }
+
+    /// Like `load_shaders`, but only loads shaders with paths relative to their
+    /// source root matching the glob `pattern`, e.g. `post/**/*.wgsl`. Includes
+    /// are resolved from anywhere.
+    pub fn load_matching(config: &Config, pattern: &str) -> Result<Shaders, SourceError> {
+        let matcher = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()?
+            .compile_matcher();
+        Shader::load_selected(config, &|path| matcher.is_match(path))
+    }
+
+    fn load_selected(
+        config: &Config,
+        select: &dyn Fn(&Path) -> bool,
+    ) -> Result<Shaders, SourceError> {
+        let mut result = vec![];
         let mut validator = config.validator();
+        for root in &config.src {
+            if !root.is_dir() {
+                return Err(SourceError::SourceDirMissing(root.clone()));
+            }
+            let provider = FileSystemProvider::for_root(config, root);
+            for mut shader in
+                Shader::load_selected_with(config, &provider, &mut validator, select)?
+            {
+                shader.root = root.clone();
+                result.push(shader);
+            }
+        }
+        Ok(Shaders::from(result))
+    }
+
+    pub fn load_shaders_from(
+        config: &Config,
+        provider: &dyn SourceProvider,
+    ) -> Result<Vec<Shader>, SourceError> {
+        Shader::load_shaders_with(config, provider, &mut config.validator())
+    }
+
+    /// Like `load_shaders_from`, but validates with a shared `validator`.
+    pub fn load_shaders_with(
+        config: &Config,
+        provider: &dyn SourceProvider,
+        validator: &mut Validator,
+    ) -> Result<Vec<Shader>, SourceError> {
+        Shader::load_selected_with(config, provider, validator, &|_| true)
+    }
+
+    fn load_selected_with(
+        config: &Config,
+        provider: &dyn SourceProvider,
+        validator: &mut Validator,
+        select: &dyn Fn(&Path) -> bool,
+    ) -> Result<Vec<Shader>, SourceError> {
+        config.validate()?;
+
+        let mut result: Vec<Shader> = vec![];
+        for mut shader in Shader::collect_selected(provider, select) {
+            shader.entry_point_name = config.entry_point_name.clone();
+            shader.bounds_check = config.bounds_check;
+            shader.glsl = config.glsl.clone();
+            shader.stages = config.stages.clone();
+            for (name, value) in &config.defines {
+                shader
+                    .defines
+                    .entry(name.clone())
+                    .or_insert_with(|| value.clone());
+            }
+            {
+                stage_span!("preprocess", shader.path);
+                preprocess::preprocess_shader_from(&mut shader, config, provider)?;
+            }
+            if shader.skip {
+                log::info!("Skipping: {}", shader.path.display());
+                continue;
+            }
+            result.push(shader);
+        }
 
         log::trace!("Working in: {}", std::env::current_dir().unwrap().display());
         for shader in &mut result {
             let path_display = shader.path.as_os_str().to_string_lossy().to_string();
-            log::debug!("Parsing: {}", &path_display);
-            if let Err(err) = shader.parse() {
+            let parsed = {
+                stage_span!("parse", shader.path);
+                log::debug!("Parsing: {}", &path_display);
+                shader.parse().map(|_| ())
+            };
+            if let Err(err) = parsed {
                 match &err {
                     #[cfg(feature = "wgsl-in")]
                     SourceError::WGSLParse(e) => {
@@ -184,37 +489,68 @@ impl Shader {
                             e.message()
                         );
                     }
-                    _ => unreachable!(""),
+                    _ => log::error!("{}: {}", shader.path.display(), err),
                 }
                 return Err(err);
             }
 
+            stage_span!("validate", shader.path);
             log::debug!("Validating: {}", &path_display);
-            shader.module_info = match validator.validate(shader.module.as_ref().unwrap())
-            {
-                Ok(info) => Some(info),
-                Err(err) => {
-                    log::error!("{}", err);
-                    return Err(SourceError::Validation(shader.path.clone()));
-                }
-            };
+            shader.validate_for(config, validator)?;
         }
 
         Ok(result)
     }
 
+    /// Runs `pass` over the parsed module and validates it again, so transforms
+    /// that produce an invalid module fail before any output is generated.
+    pub fn with_module_pass(
+        &mut self,
+        validator: &mut Validator,
+        pass: impl FnOnce(&mut Module),
+    ) -> Result<&ModuleInfo, SourceError> {
+        pass(self.module.as_mut().expect("shader must be parsed first"));
+        self.module_info = None;
+        self.validate(validator)
+    }
+
+    /// Validates the parsed module, errors are logged with their source location.
+    pub fn validate(
+        &mut self,
+        validator: &mut Validator,
+    ) -> Result<&ModuleInfo, SourceError> {
+        let module = self.module.as_ref().expect("shader must be parsed first");
+        match validator.validate(module) {
+            Ok(info) => Ok(self.module_info.insert(info)),
+            Err(err) => {
+                log_validation_error(self, &err);
+                Err(SourceError::Validation(self.path.clone()))
+            }
+        }
+    }
+
+    /// Like `validate`, but uses a dedicated validator if stages of the shader's
+    /// entry points add capabilities to the configured ones.
+    pub fn validate_for(
+        &mut self,
+        config: &Config,
+        validator: &mut Validator,
+    ) -> Result<&ModuleInfo, SourceError> {
+        let capabilities =
+            config.capabilities_for(self.entry_points().iter().map(|it| it.stage));
+        if capabilities == config.capabilities() {
+            self.validate(validator)
+        } else {
+            self.validate(&mut Validator::new(config.validation_flags(), capabilities))
+        }
+    }
+
     pub fn read(&mut self) -> Option<&ShaderCode> {
         if self.source.is_some() {
             return self.source.as_ref();
         }
 
-        let read_result = if self.lang.is_binary() {
-            std::fs::read(&self.path).map(ShaderCode::Binary)
-        } else {
-            std::fs::read_to_string(&self.path).map(ShaderCode::Text)
-        };
-
-        match read_result {
+        match ShaderCode::from_path(&self.path, self.lang) {
             Ok(shader_source) => self.source = Some(shader_source),
             Err(err) => {
                 log::warn!("Unable to read shader file: {}", self.path.display());
@@ -226,22 +562,599 @@ impl Shader {
         self.source.as_ref()
     }
 
+    /// Parses the source, `source_stage` is filled in from the entry points of
+    /// single stage modules if the file name didn't specify it. This is how
+    /// SPIR-V sources get their stage, from entry point execution models.
     pub fn parse(&mut self) -> Result<&Module, SourceError> {
-        self.lang.parse(self)
+        self.lang.parse(self)?;
+        self.apply_overrides();
+        if self.source_stage.is_none() {
+            let mut stages = self.entry_points().iter().map(|it| it.stage);
+            if let Some(stage) = stages.next() {
+                if stages.all(|other| other == stage) {
+                    self.source_stage = Some(stage);
+                }
+            }
+        }
+        Ok(self.module.as_ref().expect("shader was just parsed"))
     }
+
+    /// Bakes `overrides` into the values of matching scalar constants, which
+    /// stop being specializable.
+    fn apply_overrides(&mut self) {
+        let Shader {
+            path,
+            overrides,
+            module,
+            ..
+        } = self;
+        let module = match module {
+            Some(module) if !overrides.is_empty() => module,
+            _ => return,
+        };
+
+        let mut applied = BTreeSet::new();
+        for (_, constant) in module.constants.iter_mut() {
+            let by_id = constant.specialization.map(|id| id.to_string());
+            let (key, with) = match constant
+                .name
+                .as_deref()
+                .and_then(|name| overrides.get_key_value(name))
+                .or_else(|| by_id.and_then(|id| overrides.get_key_value(&id)))
+            {
+                Some(it) => it,
+                None => continue,
+            };
+            applied.insert(key.as_str());
+
+            let value = match &mut constant.inner {
+                ConstantInner::Scalar { value, .. } => value,
+                ConstantInner::Composite { .. } => {
+                    log::warn!(
+                        "{}: can't override composite constant {}",
+                        path.display(),
+                        key
+                    );
+                    continue;
+                }
+            };
+            match override_value(*value, *with) {
+                Some(overridden) => {
+                    *value = overridden;
+                    constant.specialization = None;
+                }
+                None => log::warn!(
+                    "{}: {} doesn't fit the type of constant {}",
+                    path.display(),
+                    with,
+                    key
+                ),
+            }
+        }
+
+        for key in overrides.keys().filter(|it| !applied.contains(it.as_str())) {
+            log::warn!("{}: no constant to override with {}", path.display(), key);
+        }
+    }
+
+    /// Entry points of the parsed module, empty if the shader wasn't parsed yet.
+    pub fn entry_points(&self) -> &[EntryPoint] {
+        match &self.module {
+            Some(module) => &module.entry_points,
+            None => &[],
+        }
+    }
+
+    /// Transpiles the shader to `via`, parses that output back and re-emits it in
+    /// the original language. Targets which require an entry point only carry the
+    /// first one through.
+    pub fn roundtrip(
+        &mut self,
+        via: ShaderLanguage,
+    ) -> Result<ShaderCode, TranspileError> {
+        let preset = ValidationPreset::default();
+        let mut validator =
+            Validator::new(preset.validation_flags(), preset.capabilities());
+        self.roundtrip_with(via, &mut validator)
+    }
+
+    /// Like `roundtrip`, but validates with a shared `validator`.
+    pub fn roundtrip_with(
+        &mut self,
+        via: ShaderLanguage,
+        validator: &mut Validator,
+    ) -> Result<ShaderCode, TranspileError> {
+        self.parse()?;
+        if self.module_info.is_none() {
+            self.validate(validator)?;
+        }
+
+        let entry_point = self.entry_points().first();
+        let mut intermediate = Shader {
+            path: self
+                .path
+                .with_extension(via.get_ext(entry_point.map(|it| it.stage))),
+            root: self.root.clone(),
+            lang: via,
+            source_stage: entry_point.map(|it| it.stage),
+            targets: None,
+            entry_names: None,
+            name: None,
+            skip: false,
+            entry_point_name: None,
+            bounds_check: self.bounds_check,
+            glsl: self.glsl.clone(),
+            stages: None,
+            defines: BTreeMap::new(),
+            variants: BTreeMap::new(),
+            variant: None,
+            overrides: BTreeMap::new(),
+            source: Some(transpile_entry(self, entry_point, via)?),
+            dependencies: vec![],
+
+            module: None,
+            module_info: None,
+        };
+
+        intermediate.parse()?;
+        intermediate.validate(validator)?;
+
+        transpile_entry(
+            &intermediate,
+            intermediate.entry_points().first(),
+            self.lang,
+        )
+    }
+
+    /// Layouts of structs used by buffers, see `layout::buffer_structs`.
+    pub fn uniform_structs(&self) -> Vec<StructLayout> {
+        self.module
+            .as_ref()
+            .map(layout::buffer_structs)
+            .unwrap_or_default()
+    }
+
+    /// Summary of entry points, bindings and buffer layouts of the module, `None`
+    /// if the shader wasn't parsed and validated yet.
+    pub fn reflect(&self) -> Option<ShaderReflection> {
+        Some(reflect::reflect(
+            self.module.as_ref()?,
+            self.module_info.as_ref()?,
+        ))
+    }
+
+    /// Entry points selected for transpilation.
+    pub fn selected_entry_points(&self) -> Vec<&EntryPoint> {
+        self.entry_points()
+            .iter()
+            .filter(
+                |entry_point| match (&self.entry_names, &entry_point.function.name) {
+                    (Some(names), Some(name)) => names.contains(name),
+                    (Some(_), None) => false,
+                    (None, _) => true,
+                },
+            )
+            .filter(|entry_point| match &self.stages {
+                Some(stages) if !stages.contains(&entry_point.stage) => {
+                    log::debug!(
+                        "Skipping {} entry point {} of {}",
+                        entry_point.stage.name(),
+                        entry_point.name,
+                        self.path.display()
+                    );
+                    false
+                }
+                _ => true,
+            })
+            .collect()
+    }
+
+    pub fn stages(&self) -> Vec<ShaderStage> {
+        let mut result: Vec<ShaderStage> = vec![];
+        for entry_point in self.entry_points() {
+            if !result.contains(&entry_point.stage) {
+                result.push(entry_point.stage);
+            }
+        }
+        result
+    }
+}
+
+/// Loaded shaders along with data derived from all of them.
+#[derive(Debug, Default)]
+pub struct Shaders {
+    shaders: Vec<Shader>,
+    /// Indices of shaders including each file, keyed by its path joined with the
+    /// shader root.
+    dependents: OnceCell<BTreeMap<PathBuf, Vec<usize>>>,
+}
+
+impl Shaders {
+    pub fn into_inner(self) -> Vec<Shader> {
+        self.shaders
+    }
+
+    /// Finds a shader by its name override, file name without extensions or
+    /// path relative to its root.
+    pub fn find(&self, name: &str) -> Option<&Shader> {
+        self.shaders.iter().find(|shader| {
+            shader.name.as_deref() == Some(name)
+                || shader.path == Path::new(name)
+                || file_prefix(&shader.path).is_some_and(|prefix| prefix == name)
+        })
+    }
+
+    pub fn iter_by_language(
+        &self,
+        lang: ShaderLanguage,
+    ) -> impl Iterator<Item = &Shader> + '_ {
+        self.shaders
+            .iter()
+            .filter(move |shader| shader.lang == lang)
+    }
+
+    /// Shaders which include `path`, given relative to the working directory.
+    pub fn dependents(&self, path: &Path) -> impl Iterator<Item = &Shader> + '_ {
+        let dependents = self.dependents.get_or_init(|| {
+            let mut result: BTreeMap<PathBuf, Vec<usize>> = BTreeMap::new();
+            for (i, shader) in self.shaders.iter().enumerate() {
+                for dependency in &shader.dependencies {
+                    let path = normalize_path(shader.root.join(dependency));
+                    result.entry(path).or_default().push(i);
+                }
+            }
+            result
+        });
+        dependents
+            .get(&normalize_path(path))
+            .into_iter()
+            .flatten()
+            .map(|&i| &self.shaders[i])
+    }
+}
+
+impl From<Vec<Shader>> for Shaders {
+    fn from(shaders: Vec<Shader>) -> Self {
+        Shaders {
+            shaders,
+            dependents: OnceCell::new(),
+        }
+    }
+}
+
+impl Deref for Shaders {
+    type Target = Vec<Shader>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.shaders
+    }
+}
+
+impl<'a> IntoIterator for &'a Shaders {
+    type Item = &'a Shader;
+    type IntoIter = std::slice::Iter<'a, Shader>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.shaders.iter()
+    }
+}
+
+impl IntoIterator for Shaders {
+    type Item = Shader;
+    type IntoIter = std::vec::IntoIter<Shader>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.shaders.into_iter()
+    }
+}
+
+/// Logs a validation error along with source locations of the spans naga
+/// attached to it.
+fn log_validation_error(shader: &Shader, err: &WithSpan<ValidationError>) {
+    let mut message = format!("{}: {}", shader.path.display(), err);
+    let mut source: Option<&dyn std::error::Error> = std::error::Error::source(err);
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    log::error!("{}", message);
+
+    let text = match shader.source.as_ref().and_then(ShaderCode::get_text) {
+        Some(text) => text,
+        None => return,
+    };
+    for (span, label) in err.spans() {
+        if !span.is_defined() || span.to_range().is_none_or(|it| it.end > text.len()) {
+            continue;
+        }
+        let location = span.location(text);
+        let line = text
+            .lines()
+            .nth(location.line_number as usize - 1)
+            .unwrap_or_default();
+        log::error!(
+            "{}:{}:{}: {}\n    {}",
+            shader.path.display(),
+            location.line_number,
+            location.line_position,
+            label,
+            line.trim_end()
+        );
+    }
+}
+
+/// `with` as a value of the same kind as `value`, `None` if it can't be
+/// represented by it.
+fn override_value(value: ScalarValue, with: f64) -> Option<ScalarValue> {
+    Some(match value {
+        ScalarValue::Float(_) => ScalarValue::Float(with),
+        ScalarValue::Bool(_) => ScalarValue::Bool(with != 0.0),
+        _ if with.fract() != 0.0 => return None,
+        ScalarValue::Sint(_) => ScalarValue::Sint(with as i64),
+        ScalarValue::Uint(_) if with >= 0.0 => ScalarValue::Uint(with as u64),
+        ScalarValue::Uint(_) => return None,
+    })
 }
 
-#[allow(unreachable_code)]
 pub(crate) fn stage_from_name(path: impl AsRef<Path>) -> Option<ShaderStage> {
+    if !cfg!(feature = "glsl-in") {
+        return None;
+    }
     let ext = path.as_ref().long_ext()?.to_ascii_lowercase();
+    ShaderStage::from_name(ext.strip_suffix(".glsl").unwrap_or(&ext))
+}
 
-    Some(match ext.as_str() {
-        #[cfg(feature = "glsl-in")]
-        "vs" | "vert" | "vs.glsl" => ShaderStage::Vertex,
-        #[cfg(feature = "glsl-in")]
-        "fs" | "frag" | "fs.glsl" => ShaderStage::Fragment,
-        #[cfg(feature = "glsl-in")]
-        "cs" | "comp" | "cs.glsl" => ShaderStage::Compute,
-        _ => return None,
-    })
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Validator with all checks and no optional capabilities.
+    #[cfg(feature = "wgsl-in")]
+    pub(crate) fn fixture_validator() -> Validator {
+        Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::empty(),
+        )
+    }
+
+    /// Reads, parses and validates a test shader, e.g. `./test/src/double.wgsl`.
+    #[cfg(feature = "wgsl-in")]
+    pub(crate) fn parsed_fixture(path: impl AsRef<Path>) -> Shader {
+        let mut shader = Shader::new(path.as_ref()).unwrap();
+        shader.read().expect("couldn't read shader");
+        shader.parse().expect("couldn't parse shader");
+        shader
+            .validate(&mut fixture_validator())
+            .expect("couldn't validate shader");
+        shader
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "wgsl-in",
+        feature = "wgsl-out",
+        feature = "spv-in",
+        feature = "spv-out"
+    ))]
+    fn wgsl_survives_spv_roundtrip() {
+        let mut shader = Shader::new("./test/src/double.wgsl").unwrap();
+        shader.read().expect("couldn't read shader");
+
+        let result = shader
+            .roundtrip(ShaderLanguage::SPV)
+            .expect("couldn't round-trip shader");
+
+        let text = result.unwrap_text();
+        assert!(text.contains("@compute"));
+        assert!(text.contains("@workgroup_size(64, 1, 1)"));
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "wgsl-in",
+        feature = "wgsl-out",
+        feature = "spv-in",
+        feature = "spv-out"
+    ))]
+    fn entry_points_can_be_renamed() {
+        let mut shader = Shader::new("./test/src/double.wgsl").unwrap();
+        shader.entry_point_name = Some("main".to_string());
+        shader.read().expect("couldn't read shader");
+
+        let result = shader
+            .roundtrip(ShaderLanguage::SPV)
+            .expect("couldn't round-trip shader");
+
+        let text = result.unwrap_text();
+        assert!(text.contains("fn main("));
+    }
+
+    #[test]
+    #[cfg(feature = "wgsl-in")]
+    fn variant_defines_cover_all_combinations() {
+        let mut shader = Shader::new("./test/src/double.wgsl").unwrap();
+        shader.variants.insert(
+            "QUALITY".to_string(),
+            vec!["1".to_string(), "2".to_string()],
+        );
+        shader.variants.insert(
+            "SHADOWS".to_string(),
+            vec!["0".to_string(), "1".to_string()],
+        );
+
+        let variants = shader.variant_defines();
+        let suffixes: Vec<&str> =
+            variants.iter().map(|(suffix, _)| suffix.as_str()).collect();
+        assert_eq!(
+            suffixes,
+            [
+                "quality_1_shadows_0",
+                "quality_1_shadows_1",
+                "quality_2_shadows_0",
+                "quality_2_shadows_1"
+            ]
+        );
+        assert_eq!(variants[1].1["QUALITY"], "1");
+        assert_eq!(variants[1].1["SHADOWS"], "1");
+    }
+
+    #[test]
+    #[cfg(feature = "wgsl-in")]
+    fn empty_variant_lists_are_ignored() {
+        let mut shader = Shader::new("./test/src/double.wgsl").unwrap();
+        let mut meta = ShaderMeta::default();
+        meta.variants.insert("QUALITY".to_string(), vec![]);
+        meta.variants
+            .insert("SHADOWS".to_string(), vec!["1".to_string()]);
+        shader.apply_meta(meta);
+
+        assert_eq!(shader.variants.len(), 1);
+        assert_eq!(shader.defines["SHADOWS"], "1");
+    }
+
+    #[test]
+    #[cfg(feature = "wgsl-in")]
+    fn overrides_are_baked_into_constants() {
+        let mut shader = Shader::new("scale.wgsl").unwrap();
+        shader.source = Some(ShaderCode::Text(
+            "let SCALE: f32 = 2.0;\n\
+             let COUNT: u32 = 4u;\n\
+             @compute @workgroup_size(1)\n\
+             fn main() {\n    let x = SCALE * f32(COUNT);\n}\n"
+                .to_string(),
+        ));
+        shader.overrides.insert("SCALE".to_string(), 0.5);
+        shader.overrides.insert("COUNT".to_string(), 1.5);
+        shader.overrides.insert("MISSING".to_string(), 1.0);
+        shader.parse().expect("couldn't parse shader");
+        shader
+            .validate(&mut fixture_validator())
+            .expect("couldn't validate shader");
+
+        let value = |name: &str| {
+            let module = shader.module.as_ref().unwrap();
+            let (_, constant) = module
+                .constants
+                .iter()
+                .find(|(_, it)| it.name.as_deref() == Some(name))
+                .unwrap();
+            match constant.inner {
+                ConstantInner::Scalar { value, .. } => value,
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(value("SCALE"), ScalarValue::Float(0.5));
+        // fractional values don't fit integer constants
+        assert_eq!(value("COUNT"), ScalarValue::Uint(4));
+    }
+
+    #[test]
+    fn binary_code_is_displayed_as_hex() {
+        let text = ShaderCode::Text("void main() {}".to_string());
+        assert_eq!(text.to_string(), "void main() {}");
+
+        let mut words = vec![0x03, 0x02, 0x23, 0x07];
+        words.extend((0..8u8).flat_map(|i| [i, 0, 0, 0xff]));
+        assert_eq!(
+            ShaderCode::Binary(words).as_text_lossy(),
+            "03022307 000000ff 010000ff 020000ff 030000ff 040000ff 050000ff 060000ff\n\
+             070000ff"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "wgsl-in")]
+    fn target_paths_follow_naming_rules() {
+        let root = crate::util::TempDir::new("starch-target-paths").unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        let config = Config::init(&root);
+
+        let mut shader = Shader::new("double.wgsl").unwrap();
+        shader.root = config.primary_src().to_path_buf();
+        let stage = Some(ShaderStage::Compute);
+        assert_eq!(
+            shader.target_relative_path(&config, ShaderLanguage::GLSL, stage),
+            PathBuf::from("double.comp.glsl")
+        );
+        assert_eq!(
+            shader.target_path(&config, ShaderLanguage::SPV, stage),
+            config.target_dir(ShaderLanguage::SPV).join("double.c.spv")
+        );
+
+        shader.variant = Some("quality_2".to_string());
+        assert_eq!(
+            shader.target_relative_path(&config, ShaderLanguage::GLSL, stage),
+            PathBuf::from("double.quality_2.comp.glsl")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "wgsl-in")]
+    fn source_stage_is_taken_from_entry_point() {
+        let unparsed = Shader::new("./test/src/double.wgsl").unwrap();
+        assert_eq!(unparsed.source_stage, None);
+        let compute = parsed_fixture("./test/src/double.wgsl");
+        assert_eq!(compute.source_stage, Some(ShaderStage::Compute));
+
+        let multi_stage = parsed_fixture("./test/src/triangle.wgsl");
+        assert_eq!(multi_stage.source_stage, None);
+    }
+
+    #[test]
+    #[cfg(all(feature = "wgsl-in", feature = "spv-in", feature = "spv-out"))]
+    fn spv_stage_is_taken_from_execution_model() {
+        let compute = parsed_fixture("./test/src/double.wgsl");
+        let spv = transpile_entry(
+            &compute,
+            Some(&compute.entry_points()[0]),
+            ShaderLanguage::SPV,
+        )
+        .unwrap();
+
+        let root = crate::util::TempDir::new("starch-spv-stage").unwrap();
+        let path = root.join("double.spv");
+        std::fs::write(&path, spv.unwrap_binary()).unwrap();
+
+        let mut shader = Shader::new(&path).unwrap();
+        assert_eq!(shader.source_stage, None);
+        shader.read().expect("couldn't read shader");
+        shader.parse().expect("couldn't parse shader");
+        assert_eq!(shader.source_stage, Some(ShaderStage::Compute));
+    }
+
+    #[test]
+    #[cfg(feature = "wgsl-in")]
+    fn module_passes_are_validated() {
+        let mut validator = fixture_validator();
+        let mut shader = parsed_fixture("./test/src/double.wgsl");
+
+        shader
+            .with_module_pass(&mut validator, |module| {
+                module.entry_points[0].name = "renamed".to_string();
+            })
+            .expect("renaming entry point broke the module");
+        assert_eq!(shader.entry_points()[0].name, "renamed");
+
+        let result = shader.with_module_pass(&mut validator, |module| {
+            module.entry_points[0].stage = ShaderStage::Vertex;
+        });
+        assert!(result.is_err());
+        assert!(shader.module_info.is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "glsl-in")]
+    fn stages_are_read_from_nested_paths() {
+        assert_eq!(stage_from_name("model.vert"), Some(ShaderStage::Vertex));
+        assert_eq!(
+            stage_from_name("post/blur.frag"),
+            Some(ShaderStage::Fragment)
+        );
+        assert_eq!(stage_from_name("ab/x.frag"), Some(ShaderStage::Fragment));
+        assert_eq!(
+            stage_from_name("aü/x.comp.glsl"),
+            Some(ShaderStage::Compute)
+        );
+        assert_eq!(stage_from_name("aü/x.glsl"), None);
+    }
 }
@@ -0,0 +1,134 @@
+use crate::config::Config;
+use crate::util::{collect_files, LogResult};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::{Path, PathBuf};
+
+/// Storage shader sources are collected and read from.
+///
+/// All paths are relative to the root of the provider.
+pub trait SourceProvider {
+    /// Returns paths of all files accepted by `filter`.
+    fn collect(&self, filter: &dyn Fn(&Path) -> bool) -> Vec<PathBuf>;
+
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>>;
+
+    fn read_to_string(&self, path: &Path) -> std::io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
+    /// Whether `path` refers to a file that can be read.
+    fn exists(&self, path: &Path) -> bool {
+        self.read(path).is_ok()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileSystemProvider {
+    pub root: PathBuf,
+    pub ignored: Vec<PathBuf>,
+}
+
+impl FileSystemProvider {
+    pub fn new(root: impl AsRef<Path>) -> FileSystemProvider {
+        FileSystemProvider {
+            root: root.as_ref().to_path_buf(),
+            ignored: vec![],
+        }
+    }
+
+    /// Provider reading from the primary source root, skipping generated output.
+    pub fn from_config(config: &Config) -> FileSystemProvider {
+        FileSystemProvider::for_root(config, config.primary_src())
+    }
+
+    /// Provider reading from one of the source roots, skipping generated output.
+    pub fn for_root(config: &Config, root: impl AsRef<Path>) -> FileSystemProvider {
+        FileSystemProvider {
+            root: root.as_ref().to_path_buf(),
+            ignored: config.out.canonicalize().into_iter().collect(),
+        }
+    }
+}
+
+/// Name of the gitignore style file in source roots excluding files from collection.
+pub const IGNORE_FILE: &str = ".starchignore";
+
+impl FileSystemProvider {
+    fn ignore_rules(&self) -> Option<Gitignore> {
+        let path = self.root.join(IGNORE_FILE);
+        if !path.is_file() {
+            return None;
+        }
+
+        let mut builder = GitignoreBuilder::new(self.root.canonicalize().ok()?);
+        if let Some(err) = builder.add(&path) {
+            log::warn!("invalid rule in {}: {}", path.display(), err);
+        }
+        builder.build().ok_or_log()
+    }
+}
+
+impl SourceProvider for FileSystemProvider {
+    fn collect(&self, filter: &dyn Fn(&Path) -> bool) -> Vec<PathBuf> {
+        let rules = self.ignore_rules();
+        let excluded = |path: &Path, is_dir: bool| {
+            rules
+                .as_ref()
+                .map(|rules| rules.matched(path, is_dir).is_ignore())
+                .unwrap_or(false)
+        };
+
+        collect_files(&self.root, |path| {
+            if path.is_dir() {
+                !self.ignored.iter().any(|ignored| ignored == path)
+                    && !excluded(path, true)
+            } else {
+                filter(path) && !excluded(path, false)
+            }
+        })
+    }
+
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        std::fs::read(self.root.join(path))
+    }
+
+    fn read_to_string(&self, path: &Path) -> std::io::Result<String> {
+        std::fs::read_to_string(self.root.join(path))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.root.join(path).is_file()
+    }
+}
+
+#[cfg(feature = "include_dir")]
+impl SourceProvider for include_dir::Dir<'_> {
+    fn collect(&self, filter: &dyn Fn(&Path) -> bool) -> Vec<PathBuf> {
+        let mut result: Vec<PathBuf> = self
+            .files()
+            .map(|file| file.path())
+            .filter(|path| filter(path))
+            .map(Path::to_path_buf)
+            .collect();
+        for dir in self.dirs() {
+            result.append(&mut dir.collect(filter));
+        }
+        result
+    }
+
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        self.get_file(path)
+            .map(|file| file.contents().to_vec())
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("no embedded file: {}", path.display()),
+                )
+            })
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.get_file(path).is_some()
+    }
+}
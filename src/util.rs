@@ -1,7 +1,10 @@
 use naga::ShaderStage;
 use std::error::Error;
 use std::ffi::OsStr;
-use std::path::{Path, PathBuf};
+use std::fmt::Display;
+use std::ops::Deref;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 fn os_str_as_u8_slice(s: &OsStr) -> &[u8] {
     unsafe { &*(s as *const OsStr as *const [u8]) }
@@ -34,21 +37,28 @@ pub fn file_prefix(path: &Path) -> Option<&OsStr> {
 }
 
 pub trait PathExt {
+    /// Everything after the first dot of the file name, e.g. `frag.glsl`.
     fn long_ext(&self) -> Option<&str>;
 }
 
 impl<T: AsRef<Path>> PathExt for T {
     fn long_ext(&self) -> Option<&str> {
-        let start = file_prefix(self.as_ref())?.len() + 1;
-        Some(&self.as_ref().to_str()?[start..])
+        let (_, ext) = split_file_at_dot(self.as_ref().file_name()?);
+        ext?.to_str()
     }
 }
 
-pub trait Name {
+/// Conversions between shader stages and their names.
+pub trait StageExt: Sized {
+    /// Full name, e.g. `Vertex`.
     fn name(&self) -> &'static str;
+    /// Abbreviated name used in file names, e.g. `vert`.
+    fn short_name(&self) -> &'static str;
+    /// Parses full, abbreviated and shorthand (`vs`) names, ignoring case.
+    fn from_name(name: &str) -> Option<Self>;
 }
 
-impl Name for ShaderStage {
+impl StageExt for ShaderStage {
     fn name(&self) -> &'static str {
         match self {
             ShaderStage::Vertex => "Vertex",
@@ -56,6 +66,141 @@ impl Name for ShaderStage {
             ShaderStage::Compute => "Compute",
         }
     }
+
+    fn short_name(&self) -> &'static str {
+        match self {
+            ShaderStage::Vertex => "vert",
+            ShaderStage::Fragment => "frag",
+            ShaderStage::Compute => "comp",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<ShaderStage> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "vertex" | "vert" | "vs" => ShaderStage::Vertex,
+            "fragment" | "frag" | "fs" => ShaderStage::Fragment,
+            "compute" | "comp" | "cs" => ShaderStage::Compute,
+            _ => return None,
+        })
+    }
+}
+
+/// Resolves `.` and `..` components without touching the file system.
+pub fn normalize_path(path: impl AsRef<Path>) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.as_ref().components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match result.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    result.pop();
+                }
+                _ => result.push(component),
+            },
+            other => result.push(other),
+        }
+    }
+    result
+}
+
+/// Path leading from `base` to `path`, both are normalized first.
+pub fn relative_path(path: impl AsRef<Path>, base: impl AsRef<Path>) -> PathBuf {
+    let path = normalize_path(path);
+    let base = normalize_path(base);
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut result = PathBuf::new();
+    for _ in base.components().skip(common) {
+        result.push("..");
+    }
+    for component in path.components().skip(common) {
+        result.push(component);
+    }
+    result
+}
+
+/// Turns `name` into a valid Rust identifier by replacing runs of illegal
+/// characters with `_` and prefixing names that start with a digit.
+pub fn sanitize_identifier(name: &str) -> String {
+    let mut result = String::with_capacity(name.len() + 1);
+    for part in name
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|part| !part.is_empty())
+    {
+        if !result.is_empty() {
+            result.push('_');
+        }
+        result.push_str(part);
+    }
+
+    if result.is_empty() || result.starts_with(char::is_numeric) {
+        result.insert(0, '_');
+    }
+    result
+}
+
+/// Hidden sibling of `path` it's written to before being moved into place.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Writes `contents` to a temporary file and renames it over `path`, so
+/// interrupted writes never leave a partial file behind.
+pub(crate) fn write_atomic(
+    path: impl AsRef<Path>,
+    contents: impl AsRef<[u8]>,
+) -> std::io::Result<()> {
+    let temp = temp_path(path.as_ref());
+    std::fs::write(&temp, contents)?;
+    std::fs::rename(temp, path)
+}
+
+/// Directory in the system temporary directory that's removed along with its
+/// contents when dropped.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(prefix: &str) -> std::io::Result<TempDir> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "{}-{}-{}",
+            prefix,
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        if path.exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        std::fs::create_dir_all(&path)?;
+        Ok(TempDir(path))
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
 }
 
 pub fn collect_files<F: Fn(&Path) -> bool>(
@@ -108,6 +253,27 @@ fn collect_files_impl<F: Fn(&Path) -> bool>(
     result
 }
 
+/// Enters a span named after a pipeline `$stage` of the shader at `$path` for
+/// the rest of the enclosing scope. Does nothing without the `tracing` feature.
+macro_rules! stage_span {
+    ($stage: literal, $path: expr) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!($stage, path = %$path.display()).entered();
+    };
+}
+pub(crate) use stage_span;
+
+/// Warns that `setting` is ignored because starch was built without `features`.
+pub(crate) fn warn_feature_disabled(setting: impl Display, features: &[&str]) {
+    let names: Vec<String> = features.iter().map(|it| format!("\"{}\"", it)).collect();
+    log::warn!(
+        "Ignoring {}: starch was built without the {} feature{}",
+        setting,
+        names.join(" and "),
+        if names.len() > 1 { "s" } else { "" }
+    );
+}
+
 pub(crate) trait LogResult<T> {
     fn ok_or_log(self) -> Option<T>;
 }
@@ -133,9 +299,48 @@ mod tests {
         let test_path = PathBuf::from("./src/");
 
         let test = collect_files(&test_path, |path| {
-            path.extension().map(|os_str| os_str.to_str()).flatten() == Some("rs")
+            path.extension().and_then(|os_str| os_str.to_str()) == Some("rs")
         });
 
-        assert!(test.len() > 0)
+        assert!(!test.is_empty())
+    }
+
+    #[test]
+    fn identifiers_are_sanitized() {
+        assert_eq!(sanitize_identifier("BLUR"), "BLUR");
+        assert_eq!(sanitize_identifier(".COMMON"), "COMMON");
+        assert_eq!(sanitize_identifier("3D_BLUR"), "_3D_BLUR");
+        assert_eq!(sanitize_identifier("MY-SHADER..V2"), "MY_SHADER_V2");
+        assert_eq!(sanitize_identifier("ŠUM"), "ŠUM");
+    }
+
+    #[test]
+    fn relative_paths_are_resolved() {
+        assert_eq!(relative_path("a/b/c", "a"), PathBuf::from("b/c"));
+        assert_eq!(relative_path("a/b", "a/c/d"), PathBuf::from("../../b"));
+        assert_eq!(relative_path("./a", "a"), PathBuf::new());
+    }
+
+    #[test]
+    fn long_extensions_are_read_from_file_names() {
+        assert_eq!("model.vert".long_ext(), Some("vert"));
+        assert_eq!("post/blur.frag.glsl".long_ext(), Some("frag.glsl"));
+        assert_eq!("aü/x.frag".long_ext(), Some("frag"));
+        assert_eq!("a.b/x".long_ext(), None);
+        assert_eq!(".hidden".long_ext(), None);
+    }
+
+    #[test]
+    fn stage_names_parse_back() {
+        for stage in [
+            ShaderStage::Vertex,
+            ShaderStage::Fragment,
+            ShaderStage::Compute,
+        ] {
+            assert_eq!(ShaderStage::from_name(stage.name()), Some(stage));
+            assert_eq!(ShaderStage::from_name(stage.short_name()), Some(stage));
+        }
+        assert_eq!(ShaderStage::from_name("FS"), Some(ShaderStage::Fragment));
+        assert_eq!(ShaderStage::from_name("geometry"), None);
     }
 }
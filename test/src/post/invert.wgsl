@@ -0,0 +1,8 @@
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let color = textureLoad(source, vec2<i32>(position.xy), 0);
+    return vec4<f32>(vec3<f32>(1.0) - color.rgb, color.a);
+}
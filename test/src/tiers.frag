@@ -0,0 +1,11 @@
+#version 450
+
+layout(location = 0) out vec4 color;
+
+void main() {
+#if QUALITY > 1
+    color = vec4(1.0);
+#else
+    color = vec4(0.5);
+#endif
+}